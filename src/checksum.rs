@@ -0,0 +1,214 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use anyhow::{anyhow, Result};
+use sha2::digest::DynDigest;
+use sha2::{Digest, Sha256, Sha512};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn from_hex(hex: &str) -> Option<Self> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        match hex.len() {
+            64 => Some(Algorithm::Sha256),
+            128 => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha512 => "SHA-512",
+        }
+    }
+}
+
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    pub digest: String,
+    pub source: String,
+}
+
+impl Checksum {
+    fn from_hex(hex: &str, source: &str) -> Option<Self> {
+        Some(Checksum {
+            algorithm: Algorithm::from_hex(hex)?,
+            digest: hex.to_ascii_lowercase(),
+            source: source.to_string(),
+        })
+    }
+
+    pub fn hasher(&self) -> Box<dyn DynDigest> {
+        match self.algorithm {
+            Algorithm::Sha256 => Box::new(Sha256::new()),
+            Algorithm::Sha512 => Box::new(Sha512::new()),
+        }
+    }
+
+    pub fn check(&self, hasher: Box<dyn DynDigest>) -> Result<()> {
+        let digest: String = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        if digest != self.digest {
+            return Err(anyhow!(io::Error::other(format!(
+                "{} mismatch, the image is corrupted or incomplete (expected {}, got {})",
+                self.algorithm.name(),
+                self.digest,
+                digest
+            ))));
+        }
+
+        Ok(())
+    }
+}
+
+// Understands GNU ("<hex>  <name>", "<hex> *<name>"), BSD ("SHA256 (<name>) = <hex>")
+// and bare ("<hex>") lines. Bare lines match any image.
+fn parse_line(line: &str, filename: &str) -> Option<String> {
+    let line = line.trim();
+
+    if let Some((head, hex)) = line.split_once(") = ") {
+        let (_, name) = head.split_once(" (")?;
+        return (name == filename).then(|| hex.trim().to_string());
+    }
+
+    match line.split_once(char::is_whitespace) {
+        Some((hex, name)) => {
+            let name = name.trim_start();
+            let name = name.strip_prefix('*').unwrap_or(name);
+            let name = name.strip_prefix("./").unwrap_or(name);
+            (name == filename).then(|| hex.to_string())
+        }
+        None => Some(line.to_string()),
+    }
+}
+
+fn parse_file(path: &Path, filename: &str) -> Result<Option<Checksum>> {
+    let source = path.file_name().unwrap_or_default().to_string_lossy();
+
+    for line in fs::read_to_string(path)?.lines() {
+        if let Some(hex) = parse_line(line, filename)
+            && let Some(checksum) = Checksum::from_hex(&hex, &source)
+        {
+            return Ok(Some(checksum));
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn find(image: &Path, arg: Option<&OsStr>) -> Result<Option<Checksum>> {
+    let filename = image.file_name().unwrap().to_string_lossy();
+
+    if let Some(arg) = arg {
+        let hex = arg.to_string_lossy();
+        if let Some(checksum) = Checksum::from_hex(&hex, "--checksum") {
+            return Ok(Some(checksum));
+        }
+
+        return match parse_file(Path::new(arg), &filename)? {
+            Some(checksum) => Ok(Some(checksum)),
+            None => Err(anyhow!(
+                "No checksum for {filename} found in {}",
+                arg.to_string_lossy()
+            )),
+        };
+    }
+
+    let dirname = image.parent().unwrap_or(Path::new(""));
+    let candidates = [
+        format!("{filename}.sha256"),
+        format!("{filename}.sha256sum"),
+        format!("{filename}.sha512"),
+        format!("{filename}.sha512sum"),
+        String::from("SHA256SUMS"),
+        String::from("SHA512SUMS"),
+        String::from("sha256sum.txt"),
+        String::from("sha512sum.txt"),
+    ];
+
+    for candidate in &candidates {
+        let path = dirname.join(candidate);
+        if !path.is_file() {
+            continue;
+        }
+
+        match parse_file(&path, &filename)? {
+            Some(checksum) => return Ok(Some(checksum)),
+            // A checksum file named after the image must cover it.
+            None if candidate.starts_with(&*filename) => {
+                return Err(anyhow!("No checksum for {filename} found in {candidate}"));
+            }
+            None => {}
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    const HEX: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test]
+    fn parse_gnu_lines() {
+        let text = format!("{HEX}  disk.img");
+        let binary = format!("{HEX} *disk.img");
+        let relative = format!("{HEX}  ./disk.img");
+
+        assert_eq!(parse_line(&text, "disk.img").as_deref(), Some(HEX));
+        assert_eq!(parse_line(&binary, "disk.img").as_deref(), Some(HEX));
+        assert_eq!(parse_line(&relative, "disk.img").as_deref(), Some(HEX));
+        assert_eq!(parse_line(&text, "other.img"), None);
+    }
+
+    #[test]
+    fn parse_bsd_lines() {
+        let line = format!("SHA256 (disk.img) = {HEX}");
+
+        assert_eq!(parse_line(&line, "disk.img").as_deref(), Some(HEX));
+        assert_eq!(parse_line(&line, "other.img"), None);
+    }
+
+    #[test]
+    fn parse_bare_lines() {
+        assert_eq!(
+            parse_line(&format!("  {HEX}\n"), "any.img").as_deref(),
+            Some(HEX)
+        );
+    }
+
+    #[test]
+    fn checksum_file_without_image() {
+        let dir = env::temp_dir().join(format!("imge-checksum-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("disk.img");
+        fs::write(dir.join("disk.img.sha256"), format!("{HEX}  other.img\n")).unwrap();
+
+        let result = find(&image, None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+    }
+}
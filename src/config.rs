@@ -0,0 +1,208 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::imge::Drive;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const SYSTEM_CONFIG: &str = "/etc/imge/config.toml";
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DriveList {
+    pub serials: Vec<String>,
+    pub wwns: Vec<String>,
+    pub models: Vec<String>,
+    pub by_id: Vec<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub auto_select: bool,
+    pub hide_protected: bool,
+    pub public_key: Option<String>,
+    // Set when `public_key` comes from the system configuration,
+    // which must not be replaced by a key given on the command line.
+    #[serde(skip)]
+    pub public_key_enforced: bool,
+    pub protected: DriveList,
+    pub allowed: DriveList,
+}
+
+// WWNs show up as "naa.5002538e...", "eui.0025..." or "0x5002538e...".
+fn normalize_wwn(wwn: &str) -> String {
+    let wwn = wwn.trim().to_ascii_lowercase();
+    let wwn = wwn.split_once('.').map_or(wwn.as_str(), |(_, id)| id);
+    wwn.strip_prefix("0x").unwrap_or(wwn).to_string()
+}
+
+impl DriveList {
+    fn is_empty(&self) -> bool {
+        self.serials.is_empty()
+            && self.wwns.is_empty()
+            && self.models.is_empty()
+            && self.by_id.is_empty()
+    }
+
+    pub fn matches(&self, drive: &Drive) -> bool {
+        (!drive.serial.is_empty() && self.serials.contains(&drive.serial))
+            || (!drive.model.is_empty() && self.models.contains(&drive.model))
+            || (!drive.wwn.is_empty()
+                && self
+                    .wwns
+                    .iter()
+                    .any(|wwn| normalize_wwn(wwn) == normalize_wwn(&drive.wwn)))
+            || drive.by_id.iter().any(|link| self.by_id.contains(link))
+    }
+}
+
+fn user_config() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(dir.join("imge/config.toml"))
+}
+
+fn read(path: &Path) -> Result<Option<Config>> {
+    match fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content)
+            .map(Some)
+            .map_err(|err| anyhow!("{}: {err}", path.display())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(anyhow!("Cannot read {}: {err}", path.display())),
+    }
+}
+
+impl Config {
+    // The system configuration is the policy of the machine, a user
+    // configuration may add protected drives but never remove any.
+    fn merge(self, user: Config) -> Config {
+        let mut protected = self.protected;
+        protected.serials.extend(user.protected.serials);
+        protected.wwns.extend(user.protected.wwns);
+        protected.models.extend(user.protected.models);
+        protected.by_id.extend(user.protected.by_id);
+
+        Config {
+            auto_select: user.auto_select,
+            hide_protected: user.hide_protected,
+            public_key: self.public_key.or(user.public_key),
+            public_key_enforced: self.public_key_enforced,
+            protected,
+            allowed: match self.allowed.is_empty() {
+                true => user.allowed,
+                false => self.allowed,
+            },
+        }
+    }
+}
+
+/// Reads `/etc/imge/config.toml` and merges `~/.config/imge/config.toml`
+/// into it, a file that exists but cannot be read is an error.
+pub fn load() -> Result<Config> {
+    let system = read(Path::new(SYSTEM_CONFIG))?.map(|mut system| {
+        system.public_key_enforced = system.public_key.is_some();
+        system
+    });
+    let user = match user_config() {
+        Some(path) => read(&path)?,
+        None => None,
+    };
+
+    Ok(match (system, user) {
+        (Some(system), Some(user)) => system.merge(user),
+        (Some(config), None) | (None, Some(config)) => config,
+        (None, None) => Config::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drive(serial: &str, wwn: &str, model: &str) -> Drive {
+        Drive {
+            serial: serial.to_string(),
+            wwn: wwn.to_string(),
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn user_config_cannot_unprotect() {
+        let mut system = parse(
+            r#"
+            public-key = "system.pub"
+            [protected]
+            serials = ["SYSTEM1"]
+            wwns = ["naa.5002538e40a1b2c3"]
+            [allowed]
+            models = ["Factory Stick"]
+            "#,
+        );
+        system.public_key_enforced = true;
+        let user = parse(
+            r#"
+            auto-select = true
+            public-key = "user.pub"
+            [protected]
+            serials = ["USER1"]
+            [allowed]
+            models = ["Any Stick"]
+            "#,
+        );
+
+        let config = system.merge(user);
+
+        assert!(config.protected.matches(&drive("SYSTEM1", "", "")));
+        assert!(config.protected.matches(&drive("USER1", "", "")));
+        assert!(config
+            .protected
+            .matches(&drive("", "0x5002538e40a1b2c3", "")));
+        assert!(config.allowed.matches(&drive("", "", "Factory Stick")));
+        assert!(!config.allowed.matches(&drive("", "", "Any Stick")));
+        assert_eq!(config.public_key.as_deref(), Some("system.pub"));
+        assert!(config.public_key_enforced);
+        assert!(config.auto_select);
+    }
+
+    #[test]
+    fn empty_user_config_keeps_protection() {
+        let system = parse("[protected]\nmodels = [\"Samsung SSD 970 EVO Plus 1TB\"]\n");
+        let user = parse("hide-protected = true\n");
+
+        let config = system.merge(user);
+
+        assert!(config
+            .protected
+            .matches(&drive("", "", "Samsung SSD 970 EVO Plus 1TB")));
+        assert!(config.allowed.is_empty());
+        assert!(config.hide_protected);
+    }
+
+    #[test]
+    fn wwn_forms_match() {
+        let list =
+            parse("[protected]\nwwns = [\"naa.5002538E40A1B2C3\", \"0x50014ee2b1c2d3e4\"]\n")
+                .protected;
+
+        assert!(list.matches(&drive("", "0x5002538e40a1b2c3", "")));
+        assert!(list.matches(&drive("", "naa.50014ee2b1c2d3e4", "")));
+        assert!(list.matches(&drive("", "50014EE2B1C2D3E4", "")));
+        assert!(!list.matches(&drive("", "0x5002538e40a1b2c4", "")));
+        assert!(!list.matches(&drive("", "", "")));
+    }
+}
@@ -0,0 +1,49 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::imge::{Failure, Phase};
+use std::io;
+
+/// Suggests what to do about an error, judging by the errno of the I/O error
+/// behind it and the phase it happened in. `None` when there is nothing
+/// better to say than the error itself.
+pub fn hint(err: &anyhow::Error) -> Option<&'static str> {
+    let errno = err
+        .chain()
+        .find_map(|err| err.downcast_ref::<io::Error>())
+        .and_then(io::Error::raw_os_error)?;
+    let phase = err.downcast_ref::<Failure>().map(|failure| failure.phase);
+
+    let hint = match errno {
+        libc::EACCES | libc::EPERM => {
+            "Run imge with sudo, or add your user to the disk group \
+             (usermod -aG disk <user>) and log in again."
+        }
+        libc::EBUSY => {
+            "The drive is in use, a partition is probably mounted or held by LVM, \
+             RAID or dm-crypt. Unmount it, or run imge with -u, and try again."
+        }
+        libc::ENODEV | libc::ENXIO | libc::ESHUTDOWN => {
+            "The drive was removed or disconnected. Plug it in again and start over."
+        }
+        libc::ENOENT if phase != Some(Phase::Open) => {
+            "The drive was removed or disconnected. Plug it in again and start over."
+        }
+        libc::ENOENT => "Check the path, or plug the drive in again if it was removed.",
+        libc::EIO if phase == Some(Phase::Read) => {
+            "The source could not be read at this offset. A failing drive or a loose \
+             cable is the usual cause, try another port or reader."
+        }
+        libc::EIO => {
+            "The drive reported an I/O error at this offset. The card or stick may be \
+             worn out or badly seated, try another port, reader or drive."
+        }
+        libc::ENOSPC => "No space left on the destination. Free some space or choose another one.",
+        libc::EFBIG => "The image does not fit on this drive, choose a larger one.",
+        libc::EROFS => "The destination is read-only, choose a writable location.",
+        _ => return None,
+    };
+
+    Some(hint)
+}
@@ -0,0 +1,267 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::imge::{self, DriveIdentity, Volume};
+use anyhow::{anyhow, Result};
+use std::env;
+use std::ffi::{CStr, OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "imge-header 1";
+
+// Covers the MBR, the primary and backup GPT and the superblocks
+// of most filesystems.
+const HEADER_SIZE: u64 = 4 * 1024 * 1024;
+
+pub struct Backup {
+    pub device: String,
+    pub created: String,
+    pub identity: DriveIdentity,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+fn state_dir() -> Result<PathBuf> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(
+            env::var_os("HOME").ok_or_else(|| anyhow!("Cannot find the home directory"))?,
+        )
+        .join(".local/state"),
+    };
+
+    Ok(dir.join("imge"))
+}
+
+fn strftime(format: &CStr) -> String {
+    let mut buffer = [0u8; 64];
+
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        let len = libc::strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            format.as_ptr(),
+            &tm,
+        );
+        String::from_utf8_lossy(&buffer[..len]).to_string()
+    }
+}
+
+/// Saves the first and last MiBs of the drive to a timestamped file
+/// under `~/.local/state/imge` and returns its path.
+pub fn backup(drive: &Volume) -> Result<PathBuf> {
+    let file = File::open(&drive.path)?;
+
+    // The backup is labelled with the drive actually read, which has to be
+    // the one selected, or it could later be restored onto the wrong drive.
+    if let Some(expected) = &drive.identity {
+        imge::check_identity(&file, expected)?;
+    }
+    let identity = match imge::identity_of(&file)? {
+        Some(identity) => identity,
+        None => DriveIdentity {
+            model: String::new(),
+            serial: String::new(),
+            size: file.metadata()?.len(),
+        },
+    };
+    let size = identity.size;
+
+    let head_len = size.min(HEADER_SIZE);
+    let tail_len = size.saturating_sub(HEADER_SIZE).min(HEADER_SIZE);
+    let mut head = vec![0u8; head_len as usize];
+    let mut tail = vec![0u8; tail_len as usize];
+    file.read_exact_at(&mut head, 0)?;
+    file.read_exact_at(&mut tail, size - tail_len)?;
+
+    let dir = state_dir()?;
+    fs::create_dir_all(&dir).map_err(|err| anyhow!("Cannot create {}: {err}", dir.display()))?;
+
+    let device = drive.path.to_string_lossy();
+    let name = Path::new(&drive.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let path = dir.join(format!("{}-{name}.header", strftime(c"%Y%m%d-%H%M%S")));
+
+    let mut backup =
+        File::create(&path).map_err(|err| anyhow!("Cannot create {}: {err}", path.display()))?;
+    writeln!(backup, "{HEADER}")?;
+    writeln!(backup, "device {device}")?;
+    writeln!(backup, "created {}", strftime(c"%Y-%m-%d %H:%M:%S"))?;
+    writeln!(backup, "model {}", identity.model)?;
+    writeln!(backup, "serial {}", identity.serial)?;
+    writeln!(backup, "size {}", identity.size)?;
+    writeln!(backup, "head {head_len}")?;
+    writeln!(backup, "tail {tail_len}")?;
+    writeln!(backup)?;
+    backup.write_all(&head)?;
+    backup.write_all(&tail)?;
+    backup.sync_all()?;
+
+    Ok(path)
+}
+
+pub fn read(path: &OsStr) -> Result<Backup> {
+    let name = path.to_string_lossy();
+    let invalid = || anyhow!("{name} is not a valid imge header backup");
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid());
+        }
+        let line = line.trim_end_matches('\n').to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+
+    if lines.first().map(String::as_str) != Some(HEADER) {
+        return Err(invalid());
+    }
+
+    let mut backup = Backup {
+        device: String::new(),
+        created: String::new(),
+        identity: DriveIdentity {
+            model: String::new(),
+            serial: String::new(),
+            size: 0,
+        },
+        head: Vec::new(),
+        tail: Vec::new(),
+    };
+    let (mut head_len, mut tail_len) = (0, 0);
+
+    for line in &lines[1..] {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "device" => backup.device = value.to_string(),
+            "created" => backup.created = value.to_string(),
+            "model" => backup.identity.model = value.to_string(),
+            "serial" => backup.identity.serial = value.to_string(),
+            "size" => backup.identity.size = value.parse().map_err(|_| invalid())?,
+            "head" => head_len = value.parse().map_err(|_| invalid())?,
+            "tail" => tail_len = value.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        }
+    }
+
+    // Checked before allocating, the lengths come from the file itself.
+    if head_len == 0
+        || head_len > HEADER_SIZE
+        || tail_len > HEADER_SIZE
+        || head_len
+            .checked_add(tail_len)
+            .is_none_or(|len| len > backup.identity.size)
+    {
+        return Err(invalid());
+    }
+
+    backup.head = vec![0u8; head_len as usize];
+    backup.tail = vec![0u8; tail_len as usize];
+    reader.read_exact(&mut backup.head).map_err(|_| invalid())?;
+    reader.read_exact(&mut backup.tail).map_err(|_| invalid())?;
+
+    Ok(backup)
+}
+
+/// Writes a header backup back to the drive it was taken from.
+pub fn restore(backup: &Backup, drive: &OsString) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_DSYNC | libc::O_EXCL)
+        .open(drive)
+        .map_err(|err| anyhow!("Cannot open {}: {err}", drive.to_string_lossy()))?;
+
+    let matches = match imge::identity_of(&file)? {
+        Some(identity) => identity == backup.identity,
+        None => file.metadata()?.len() == backup.identity.size,
+    };
+    if !matches {
+        return Err(anyhow!(
+            "{} is not the drive the header was saved from ({} {}, {})",
+            drive.to_string_lossy(),
+            backup.identity.model,
+            backup.identity.serial,
+            imge::humanize(backup.identity.size)
+        ));
+    }
+
+    let size = backup.identity.size;
+    file.write_all_at(&backup.head, 0)?;
+    file.write_all_at(&backup.tail, size - backup.tail.len() as u64)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn read_bytes(name: &str, content: &[u8]) -> Result<Backup> {
+        let path = env::temp_dir().join(format!("imge-{}-{name}", process::id()));
+        fs::write(&path, content).unwrap();
+        let backup = read(path.as_os_str());
+        fs::remove_file(&path).unwrap();
+        backup
+    }
+
+    fn backup_file() -> Vec<u8> {
+        let mut content = format!(
+            "{HEADER}\ndevice /dev/sdx\ncreated 2026-01-01 00:00:00\n\
+             model Flash Disk\nserial 0123\nsize 8192\nhead 4096\ntail 4096\n\n"
+        )
+        .into_bytes();
+        content.extend([1u8; 4096]);
+        content.extend([2u8; 4096]);
+        content
+    }
+
+    #[test]
+    fn read_complete() {
+        let backup = read_bytes("complete.header", &backup_file()).unwrap();
+
+        assert_eq!(backup.device, "/dev/sdx");
+        assert_eq!(backup.identity.model, "Flash Disk");
+        assert_eq!(backup.identity.serial, "0123");
+        assert_eq!(backup.identity.size, 8192);
+        assert_eq!(backup.head, [1u8; 4096]);
+        assert_eq!(backup.tail, [2u8; 4096]);
+    }
+
+    #[test]
+    fn read_truncated() {
+        let content = backup_file();
+
+        assert!(read_bytes("truncated-data.header", &content[..content.len() - 1]).is_err());
+        assert!(read_bytes("truncated-header.header", &content[..40]).is_err());
+        assert!(read_bytes("empty.header", b"").is_err());
+
+        let huge = format!(
+            "{HEADER}\nsize {}\nhead {}\ntail 0\n\n",
+            u64::MAX,
+            u64::MAX - 1
+        );
+        assert!(read_bytes("huge.header", huge.as_bytes()).is_err());
+        let overflow = format!(
+            "{HEADER}\nsize {}\nhead 4096\ntail {}\n\n",
+            u64::MAX,
+            u64::MAX
+        );
+        assert!(read_bytes("overflow.header", overflow.as_bytes()).is_err());
+    }
+}
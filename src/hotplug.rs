@@ -0,0 +1,155 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::sysfs;
+use std::fs;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// udev creates the /dev/disk/by-id links and reads the model a moment after
+// the kernel event, so the list is refreshed once more after this delay.
+const SETTLE_DELAY: Duration = Duration::from_secs(1);
+
+/// Notices block devices being added, removed or changing media, from kernel
+/// uevents or, where the netlink socket is unavailable, by polling sysfs.
+pub struct Hotplug {
+    socket: Option<OwnedFd>,
+    settle: Option<Instant>,
+    snapshot: Vec<(String, String)>,
+    polled: Instant,
+}
+
+impl Default for Hotplug {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn uevent_socket() -> Option<OwnedFd> {
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        );
+        if fd < 0 {
+            return None;
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+
+        let mut addr: libc::sockaddr_nl = mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = 1;
+
+        let result = libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        );
+        if result != 0 {
+            return None;
+        }
+
+        Some(socket)
+    }
+}
+
+// Kernel uevents are "action@devpath" followed by NUL separated KEY=value pairs.
+fn is_block_event(message: &[u8]) -> bool {
+    let mut fields = message.split(|byte| *byte == 0);
+
+    fields.next().is_some_and(|header| header.contains(&b'@'))
+        && fields.any(|field| field == b"SUBSYSTEM=block")
+}
+
+// Names and sizes of all block devices, a change in media shows up
+// as a change in size.
+fn snapshot() -> Vec<(String, String)> {
+    let mut snapshot: Vec<(String, String)> = fs::read_dir("/sys/block")
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| {
+                    let size = sysfs::read(entry.path().join("size")).unwrap_or_default();
+                    (entry.file_name().to_string_lossy().to_string(), size)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    snapshot.sort();
+    snapshot
+}
+
+impl Hotplug {
+    pub fn new() -> Self {
+        Hotplug {
+            socket: uevent_socket(),
+            settle: None,
+            snapshot: snapshot(),
+            polled: Instant::now(),
+        }
+    }
+
+    fn received(&self, socket: &OwnedFd) -> bool {
+        let mut buffer = [0u8; 8192];
+        let mut received = false;
+
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if len <= 0 {
+                break;
+            }
+
+            received |= is_block_event(&buffer[..len as usize]);
+        }
+
+        received
+    }
+
+    fn polled(&mut self) -> bool {
+        if self.polled.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.polled = Instant::now();
+
+        let snapshot = snapshot();
+        if snapshot == self.snapshot {
+            return false;
+        }
+
+        self.snapshot = snapshot;
+        true
+    }
+
+    /// Whether the drive list should be refreshed.
+    pub fn changed(&mut self) -> bool {
+        let changed = match &self.socket {
+            Some(socket) => self.received(socket),
+            None => self.polled(),
+        };
+
+        if changed {
+            self.settle = Some(Instant::now() + SETTLE_DELAY);
+            return true;
+        }
+
+        if self.settle.is_some_and(|settle| settle <= Instant::now()) {
+            self.settle = None;
+            return true;
+        }
+
+        false
+    }
+}
@@ -2,26 +2,73 @@
 //  License, v. 2.0. If a copy of the MPL was not distributed with this
 //  file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use crate::checksum::Checksum;
+use crate::config::Config;
+use crate::manifest::{self, Manifest, ManifestWriter};
+use crate::probe;
+use crate::signature::Signature;
+use crate::sysfs;
 use anyhow::{anyhow, Result};
-use std::alloc::{alloc, Layout};
-use std::ffi::OsString;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Write};
-use std::os::unix::fs::OpenOptionsExt;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::collections::BTreeSet;
+use std::ffi::{CString, OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Condvar, LockResult, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 const BLOCK_SIZE: usize = 1024 * 1024;
 
+/// Resolution of the throughput history.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct Partition {
+    pub name: OsString,
+    pub size: u64,
+    pub fstype: String,
+    pub label: String,
+    pub uuid: String,
+    pub mountpoint: Option<String>,
+}
+
+#[derive(Default)]
 pub struct Drive {
     pub name: OsString,
     pub model: String,
     pub serial: String,
+    pub wwn: String,
+    pub by_id: Vec<String>,
     pub is_removable: bool,
     pub is_mounted: bool,
+    pub is_system: bool,
+    pub is_locked: bool,
+    pub holders: Vec<String>,
+    pub partitions: Vec<Partition>,
+    pub details: sysfs::Details,
+    pub size: u64,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct DriveIdentity {
+    pub model: String,
+    pub serial: String,
     pub size: u64,
 }
 
+impl Drive {
+    pub fn identity(&self) -> DriveIdentity {
+        DriveIdentity {
+            model: self.model.clone(),
+            serial: self.serial.clone(),
+            size: self.size,
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum VolumeType {
     Image,
@@ -43,14 +90,79 @@ pub struct Volume {
     pub path: OsString,
     pub size: Option<u64>,
     pub compression: Compression,
+    pub identity: Option<DriveIdentity>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    Open,
+    Read,
+    Write,
+    Verify,
+}
+
+/// An I/O error together with what was being done to which file and where,
+/// so that it can be explained to the user.
+#[derive(Debug)]
+pub struct Failure {
+    pub phase: Phase,
+    pub path: OsString,
+    pub offset: u64,
+    pub source: io::Error,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path.to_string_lossy();
+        let action = match self.phase {
+            Phase::Open => return write!(f, "Opening {path} failed: {}", self.source),
+            Phase::Read => "Reading",
+            Phase::Write => "Writing",
+            Phase::Verify => "Verifying",
+        };
+
+        write!(
+            f,
+            "{action} {path} failed at offset {} ({}): {}",
+            self.offset,
+            humanize(self.offset),
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for Failure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn failure(phase: Phase, path: &OsStr, offset: u64, err: io::Error) -> anyhow::Error {
+    anyhow!(Failure {
+        phase,
+        path: path.to_os_string(),
+        offset,
+        source: err,
+    })
 }
 
 #[derive(Default)]
 pub struct Progress {
     pub size: u64,
     pub done: u64,
-    pub secs: u64,
+    // Also counts data that is read only to skip over it, so that
+    // a busy worker can be told apart from a stalled one.
+    pub read: u64,
+    pub time: Duration,
+    pub history: Vec<u64>,
     pub finished: bool,
+    pub cancelled: bool,
+    pub paused: bool,
+    // Syncing an image file may take long without any progress to show.
+    pub flushing: bool,
+    pub started: Option<Instant>,
+    pub paused_at: Option<Instant>,
+    pub paused_for: Duration,
 }
 
 impl Progress {
@@ -61,32 +173,267 @@ impl Progress {
             self.done as f64 / self.size as f64
         }
     }
+
+    /// Time spent in the current phase, not counting pauses.
+    pub fn elapsed(&self) -> Duration {
+        let Some(started) = self.started else {
+            return Duration::ZERO;
+        };
+
+        let now = self.paused_at.unwrap_or_else(Instant::now);
+        now.duration_since(started).saturating_sub(self.paused_for)
+    }
+
+    fn record(&mut self, len: u64) {
+        let sample = (self.elapsed().as_millis() / SAMPLE_INTERVAL.as_millis()) as usize;
+        if self.history.len() <= sample {
+            self.history.resize(sample + 1, 0);
+        }
+
+        self.done += len;
+        self.read += len;
+        self.history[sample] += len;
+    }
+
+    /// Bytes per second for every sample interval up to now, including
+    /// the intervals in which nothing was transferred.
+    pub fn throughput(&self) -> Vec<u64> {
+        let samples = (self.elapsed().as_millis() / SAMPLE_INTERVAL.as_millis()) as usize + 1;
+        let per_second = Duration::from_secs(1).as_millis() / SAMPLE_INTERVAL.as_millis();
+
+        let mut throughput: Vec<u64> = self
+            .history
+            .iter()
+            .map(|bytes| bytes * per_second as u64)
+            .collect();
+        throughput.resize(samples.max(throughput.len()), 0);
+        throughput
+    }
+
+    pub fn average_speed(&self) -> u64 {
+        match self.elapsed().as_secs_f64() {
+            secs if secs > 0.0 => (self.done as f64 / secs) as u64,
+            _ => 0,
+        }
+    }
+
+    /// Throughput over the last two seconds, leaving out the interval
+    /// still in progress.
+    pub fn current_speed(&self) -> u64 {
+        let throughput = self.throughput();
+        let complete = &throughput[..throughput.len() - 1];
+        let recent = &complete[complete.len().saturating_sub(4)..];
+
+        if recent.is_empty() {
+            self.average_speed()
+        } else {
+            recent.iter().sum::<u64>() / recent.len() as u64
+        }
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        let speed = self.average_speed();
+        if self.size == 0 || speed == 0 {
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(
+            self.size.saturating_sub(self.done) as f64 / speed as f64,
+        ))
+    }
 }
 
-pub type ProgressMutex = Arc<Mutex<Progress>>;
+/// Progress shared between the UI and a worker, which blocks on `resumed`
+/// while the transfer is paused.
+#[derive(Default)]
+pub struct SharedProgress {
+    progress: Mutex<Progress>,
+    resumed: Condvar,
+}
 
-pub fn list_drives(all_drives: bool) -> Result<Vec<Drive>> {
-    let mut drives = Vec::new();
+impl SharedProgress {
+    pub fn new(progress: Progress) -> ProgressMutex {
+        Arc::new(SharedProgress {
+            progress: Mutex::new(progress),
+            resumed: Condvar::new(),
+        })
+    }
 
-    for device in drives::get_devices()? {
-        let mut is_mounted = false;
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, Progress>> {
+        self.progress.lock()
+    }
 
-        for partition in device.partitions {
-            if partition.mountpoint.is_some() {
-                is_mounted = true;
-                break;
-            }
+    // Bytes processed so far, where a failing block starts.
+    fn done(&self) -> u64 {
+        self.progress.lock().unwrap().done
+    }
+
+    fn start(&self) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.started = Some(Instant::now());
+    }
+
+    // Called by workers after each block, returns once the transfer
+    // is not paused anymore or has been cancelled.
+    fn checkpoint(&self, len: usize) -> MutexGuard<'_, Progress> {
+        let mut progress = self.progress.lock().unwrap();
+        progress.record(len as u64);
+        self.wait(progress)
+    }
+
+    // Like `checkpoint`, for a block that is read but does not count
+    // as done, such as one decompressed to reach the next sample.
+    fn skipped(&self, len: usize) -> MutexGuard<'_, Progress> {
+        let mut progress = self.progress.lock().unwrap();
+        progress.read += len as u64;
+        self.wait(progress)
+    }
+
+    fn wait<'a>(&self, progress: MutexGuard<'a, Progress>) -> MutexGuard<'a, Progress> {
+        self.resumed
+            .wait_while(progress, |progress| progress.paused && !progress.cancelled)
+            .unwrap()
+    }
+
+    pub fn toggle_pause(&self) {
+        let mut progress = self.progress.lock().unwrap();
+
+        if let Some(paused_at) = progress.paused_at.take() {
+            progress.paused_for += paused_at.elapsed();
+            progress.paused = false;
+            self.resumed.notify_all();
+        } else {
+            progress.paused_at = Some(Instant::now());
+            progress.paused = true;
         }
+    }
+
+    pub fn cancel(&self) {
+        self.progress.lock().unwrap().cancelled = true;
+        self.resumed.notify_all();
+    }
+}
+
+pub type ProgressMutex = Arc<SharedProgress>;
+
+/// Hidden file next to the image that drive-to-image copies are written to
+/// before being renamed into place.
+pub fn temporary_path(image: &OsStr) -> OsString {
+    let path = Path::new(image);
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{filename}.imge"))
+        .into_os_string()
+}
+
+/// Space available to unprivileged users on the filesystem holding `path`.
+pub fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+fn by_id_links(name: &str) -> Vec<String> {
+    let mut links: Vec<String> = fs::read_dir("/dev/disk/by-id")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| {
+                    fs::canonicalize(entry.path())
+                        .is_ok_and(|path| path == Path::new("/dev").join(name))
+                })
+                .map(|entry| entry.path().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    links.sort();
+    links
+}
+
+fn partitions(drive: &Drive, partitions: Vec<drives::Partition>) -> Vec<Partition> {
+    let mut result: Vec<Partition> = partitions
+        .into_iter()
+        .map(|partition| {
+            let name = OsString::from(format!("/dev/{}", partition.name));
+            let filesystem = probe::probe(&name).unwrap_or_default();
+
+            Partition {
+                fstype: match &partition.mountpoint {
+                    Some(mount) if filesystem.fstype.is_empty() => mount.filesystem.clone(),
+                    _ => filesystem.fstype,
+                },
+                label: filesystem.label,
+                uuid: filesystem.uuid,
+                mountpoint: partition.mountpoint.map(|mount| mount.mountpoint),
+                size: partition.size.get_raw_size() * 512,
+                name,
+            }
+        })
+        .collect();
 
-        if device.is_removable || all_drives {
-            drives.push(Drive {
+    // A filesystem on the whole drive, as on many floppy-like sticks.
+    if result.is_empty()
+        && drive.size > 0
+        && let Some(filesystem) = probe::probe(&drive.name)
+    {
+        result.push(Partition {
+            name: drive.name.clone(),
+            size: drive.size,
+            fstype: filesystem.fstype,
+            label: filesystem.label,
+            uuid: filesystem.uuid,
+            mountpoint: None,
+        });
+    }
+
+    result
+}
+
+pub fn list_drives(all_drives: bool, factory: bool, config: &Config) -> Result<Vec<Drive>> {
+    let mut drives = Vec::new();
+    let system_disks = sysfs::system_disks();
+
+    for mut device in drives::get_devices()? {
+        let is_mounted = device
+            .partitions
+            .iter()
+            .any(|partition| partition.mountpoint.is_some());
+
+        if device.is_removable || all_drives || factory {
+            let mut drive = Drive {
                 name: OsString::from(format!("/dev/{}", device.name)),
                 model: device.model.unwrap_or_default(),
                 serial: device.serial.unwrap_or_default(),
+                wwn: sysfs::wwn(&device.name),
+                by_id: by_id_links(&device.name),
                 is_removable: device.is_removable,
                 is_mounted,
+                is_system: system_disks.contains(&device.name),
+                is_locked: false,
+                holders: sysfs::holders(&device.name),
+                partitions: Vec::new(),
+                details: sysfs::details(&device.name),
                 size: device.size.get_raw_size() * 512,
-            });
+            };
+
+            // In factory mode only allowed drives are offered at all.
+            if factory && !config.allowed.matches(&drive) {
+                continue;
+            }
+
+            drive.is_locked = config.protected.matches(&drive);
+            if drive.is_locked && config.hide_protected {
+                continue;
+            }
+
+            device.partitions.sort_by_key(|partition| partition.number);
+            drive.partitions = partitions(&drive, device.partitions);
+
+            drives.push(drive);
         }
     }
 
@@ -94,8 +441,128 @@ pub fn list_drives(all_drives: bool) -> Result<Vec<Drive>> {
     Ok(drives)
 }
 
+pub fn resolve_drive(
+    arg: &OsString,
+    all_drives: bool,
+    factory: bool,
+    config: &Config,
+) -> Result<OsString> {
+    let path = fs::canonicalize(arg).unwrap_or_default();
+    let candidates: Vec<Drive> = list_drives(true, factory, config)?
+        .into_iter()
+        .filter(|drive| {
+            path.as_os_str() == drive.name || (!drive.serial.is_empty() && *arg == *drive.serial)
+        })
+        .collect();
+
+    let drive = match candidates.as_slice() {
+        [drive] => drive,
+        [] => {
+            return Err(anyhow!(
+                "Drive {} not found, expected a device path, /dev/disk/by-id link or serial number",
+                arg.to_string_lossy()
+            ))
+        }
+        _ => {
+            return Err(anyhow!(
+                "Serial number {} matches more than one drive",
+                arg.to_string_lossy()
+            ))
+        }
+    };
+
+    // The allowlist of --factory already admits non-removable drives.
+    if !drive.is_removable && !all_drives && !factory {
+        return Err(anyhow!(
+            "{} is a non-removable drive, use -a to allow it",
+            drive.name.to_string_lossy()
+        ));
+    }
+
+    Ok(drive.name.clone())
+}
+
+/// Unmounts every filesystem on the drive, through udisks when it is around
+/// so that desktops notice, and returns the detached mountpoints.
+pub fn unmount(drive: &OsString) -> Result<Vec<String>> {
+    let disk = Path::new(drive).file_name().unwrap().to_string_lossy();
+    let mounted = sysfs::mounts_of(&disk);
+
+    for (source, mountpoint) in mounted.iter().rev() {
+        let udisks = Command::new("udisksctl")
+            .args(["unmount", "--no-user-interaction", "-b", source])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+
+        if !udisks {
+            let path = CString::new(mountpoint.as_str())?;
+            unsafe { libc::umount2(path.as_ptr(), 0) };
+        }
+    }
+
+    let still_mounted: Vec<String> = sysfs::mounts_of(&disk)
+        .into_iter()
+        .map(|(_, mountpoint)| mountpoint)
+        .collect();
+
+    if !still_mounted.is_empty() {
+        return Err(anyhow!(io::Error::other(format!(
+            "Cannot unmount {}",
+            still_mounted.join(", ")
+        ))));
+    }
+
+    Ok(mounted
+        .into_iter()
+        .map(|(_, mountpoint)| mountpoint)
+        .collect())
+}
+
+/// Model, serial and size of an open block device, `None` for other files.
+pub fn identity_of(file: &File) -> Result<Option<DriveIdentity>> {
+    let metadata = file.metadata()?;
+    if !metadata.file_type().is_block_device() {
+        return Ok(None);
+    }
+
+    let path = sysfs::devno_path(metadata.rdev());
+    Ok(Some(DriveIdentity {
+        model: sysfs::read(path.join("device/model")).unwrap_or_default(),
+        serial: sysfs::read(path.join("device/serial")).unwrap_or_default(),
+        size: sysfs::read(path.join("size"))
+            .and_then(|size| size.parse::<u64>().ok())
+            .unwrap_or_default()
+            * 512,
+    }))
+}
+
+// Makes sure the device just opened is the one selected by the user,
+// not another drive that got the same name after a replug.
+pub fn check_identity(file: &File, expected: &DriveIdentity) -> Result<()> {
+    let Some(actual) = identity_of(file)? else {
+        return Ok(());
+    };
+
+    if actual != *expected {
+        return Err(anyhow!(io::Error::other(format!(
+            "The drive has changed since it was selected, \
+             expected {} {} ({}), found {} {} ({})",
+            expected.model,
+            expected.serial,
+            humanize(expected.size),
+            actual.model,
+            actual.serial,
+            humanize(actual.size)
+        ))));
+    }
+
+    Ok(())
+}
+
 fn open_for_reading(vol: &Volume) -> Result<Box<dyn Read>> {
-    let file = File::open(&vol.path)?;
+    let file = File::open(&vol.path).map_err(|err| failure(Phase::Open, &vol.path, 0, err))?;
 
     let file: Box<dyn Read> = match vol.compression {
         Compression::None => Box::new(file),
@@ -108,15 +575,69 @@ fn open_for_reading(vol: &Volume) -> Result<Box<dyn Read>> {
     Ok(file)
 }
 
-fn open_for_writing(vol: &Volume) -> Result<Box<dyn Write>> {
+// A destination whose encoder trailer and buffers must be flushed explicitly,
+// so that errors are not lost in `Drop`.
+trait Sink: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl Sink for File {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+impl Sink for flate2::write::GzEncoder<File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.sync_all()
+    }
+}
+
+impl Sink for bzip2::write::BzEncoder<File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.sync_all()
+    }
+}
+
+impl Sink for xz2::write::XzEncoder<File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.sync_all()
+    }
+}
+
+impl Sink for zstd::stream::write::Encoder<'_, File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.sync_all()
+    }
+}
+
+fn open_for_writing(vol: &Volume) -> Result<Box<dyn Sink>> {
     let mut options = OpenOptions::new();
-    let mut options = options.create(true).write(true).truncate(true);
+    let mut options = options.write(true);
     if vol.vtype == VolumeType::Drive {
-        options = options.custom_flags(libc::O_DSYNC)
+        // Without O_CREAT, O_EXCL fails with EBUSY while the drive is held
+        // by a mount, md, LVM or dm-crypt.
+        options = options.custom_flags(libc::O_DSYNC | libc::O_EXCL)
+    } else {
+        options = options.create(true).truncate(true);
+    }
+    let file = options
+        .open(&vol.path)
+        .map_err(|err| match err.raw_os_error() {
+            Some(libc::EROFS) if vol.vtype == VolumeType::Drive => failure(
+                Phase::Open,
+                &vol.path,
+                0,
+                io::Error::other(write_protected(&vol.path)),
+            ),
+            _ => failure(Phase::Open, &vol.path, 0, err),
+        })?;
+
+    if let Some(identity) = &vol.identity {
+        check_identity(&file, identity)?;
     }
-    let file = options.open(&vol.path)?;
 
-    let file: Box<dyn Write> = match vol.compression {
+    let file: Box<dyn Sink> = match vol.compression {
         Compression::None => Box::new(file),
         Compression::Gzip => Box::new(flate2::write::GzEncoder::new(
             file,
@@ -127,86 +648,435 @@ fn open_for_writing(vol: &Volume) -> Result<Box<dyn Write>> {
             bzip2::Compression::default(),
         )),
         Compression::Xz => Box::new(xz2::write::XzEncoder::new(file, 3)),
-        Compression::Zstd => Box::new(
-            zstd::stream::write::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?.auto_finish(),
-        ),
+        Compression::Zstd => Box::new(zstd::stream::write::Encoder::new(
+            file,
+            zstd::DEFAULT_COMPRESSION_LEVEL,
+        )?),
     };
 
     Ok(file)
 }
 
-pub fn copy(src: &Volume, dest: &Volume, progress_mutex: &ProgressMutex) -> Result<()> {
+pub fn validate(
+    path: &OsString,
+    checksum: Option<&Checksum>,
+    signature: Option<&Signature>,
+    progress_mutex: &ProgressMutex,
+) -> Result<()> {
+    let mut file = File::open(path).map_err(|err| failure(Phase::Open, path, 0, err))?;
+    let mut hasher = checksum.map(|checksum| checksum.hasher());
+    let mut verifier = signature
+        .map(|signature| signature.verifier())
+        .transpose()?;
+    let mut buffer = vec![0u8; BLOCK_SIZE];
+    progress_mutex.start();
+
+    loop {
+        let len = file
+            .read(&mut buffer)
+            .map_err(|err| failure(Phase::Read, path, progress_mutex.done(), err))?;
+        if len == 0 {
+            break;
+        }
+
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..len]);
+        }
+        if let Some(verifier) = verifier.as_mut() {
+            verifier.update(&buffer[..len]);
+        }
+
+        let mut progress = progress_mutex.checkpoint(len);
+
+        if progress.cancelled {
+            progress.finished = true;
+            return Ok(());
+        }
+    }
+
+    if let (Some(checksum), Some(hasher)) = (checksum, hasher) {
+        checksum.check(hasher)?;
+    }
+
+    if let (Some(signature), Some(verifier)) = (signature, verifier) {
+        verifier.finalize().map_err(|_| {
+            anyhow!(io::Error::other(format!(
+                "Signature verification failed, the image does not match {}",
+                signature.source
+            )))
+        })?;
+    }
+
+    let mut progress = progress_mutex.lock().unwrap();
+    progress.time = progress.elapsed();
+    progress.finished = true;
+
+    Ok(())
+}
+
+pub fn copy(
+    src: &Volume,
+    dest: &Volume,
+    manifest: Option<&OsString>,
+    progress_mutex: &ProgressMutex,
+) -> Result<()> {
     if src.vtype == VolumeType::Image
         && src.size.is_some()
         && dest.size.is_some()
         && src.size > dest.size
     {
-        return Err(anyhow!(io::Error::other("File too large (os error 27)")));
+        return Err(anyhow!(io::Error::from_raw_os_error(libc::EFBIG)));
     }
 
     let mut srcfile = open_for_reading(src)?;
     let mut destfile = open_for_writing(dest)?;
+    let mut writer = manifest
+        .map(|path| ManifestWriter::create(path))
+        .transpose()?;
     let mut buffer = [0u8; BLOCK_SIZE];
-    let timer = Instant::now();
+    progress_mutex.start();
 
     loop {
-        let len = srcfile.read(&mut buffer)?;
+        let len = srcfile
+            .read(&mut buffer)
+            .map_err(|err| failure(Phase::Read, &src.path, progress_mutex.done(), err))?;
         if len == 0 {
             break;
         }
 
-        destfile.write_all(&buffer[..len])?;
+        destfile
+            .write_all(&buffer[..len])
+            .map_err(|err| write_error(dest, err, progress_mutex))?;
+
+        if let Some(writer) = writer.as_mut() {
+            writer.update(&buffer[..len])?;
+        }
 
-        let mut progress = progress_mutex.lock().unwrap();
-        progress.done += len as u64;
+        let progress = progress_mutex.checkpoint(len);
 
-        if progress.size > 0 && progress.size == progress.done {
+        if progress.cancelled || (progress.size > 0 && progress.size == progress.done) {
             break;
         }
     }
 
+    // Flushed and synced even when cancelled, so the device is left
+    // in a consistent state.
+    if dest.vtype == VolumeType::Image {
+        progress_mutex.lock().unwrap().flushing = true;
+    }
+    destfile
+        .finish()
+        .map_err(|err| write_error(dest, err, progress_mutex))?;
+
     let mut progress = progress_mutex.lock().unwrap();
-    progress.secs = timer.elapsed().as_secs();
+
+    if let Some(writer) = writer {
+        if progress.cancelled {
+            let _ = fs::remove_file(manifest.unwrap());
+        } else {
+            writer.finish()?;
+        }
+    }
+
+    progress.time = progress.elapsed();
     progress.finished = true;
 
     Ok(())
 }
 
+/// Explains how to get a write-protected drive writable again.
+pub fn write_protected(drive: &OsStr) -> String {
+    let name = Path::new(drive)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    format!(
+        "{name} is write-protected. Slide the lock switch of the SD card or its adapter \
+         away from LOCK and plug it in again. eMMC boot partitions are unlocked with \
+         echo 0 > /sys/block/{name}/force_ro"
+    )
+}
+
+// A full filesystem leaves a useless partial image behind, remove it
+// so the space is freed again.
+fn write_error(dest: &Volume, err: io::Error, progress_mutex: &ProgressMutex) -> anyhow::Error {
+    let done = progress_mutex.done();
+
+    // Some card readers only report the lock switch once the first write fails.
+    if matches!(err.raw_os_error(), Some(libc::EROFS | libc::EPERM))
+        && dest.vtype == VolumeType::Drive
+    {
+        let err = io::Error::other(write_protected(&dest.path));
+        return failure(Phase::Write, &dest.path, done, err);
+    }
+
+    if err.raw_os_error() != Some(libc::ENOSPC) || dest.vtype != VolumeType::Image {
+        return failure(Phase::Write, &dest.path, done, err);
+    }
+
+    if fs::metadata(&dest.path).is_ok_and(|metadata| metadata.is_file()) {
+        let _ = fs::remove_file(&dest.path);
+    }
+
+    anyhow!(io::Error::other(format!(
+        "The destination filesystem is full after {} of data, the partial image was removed",
+        humanize(done)
+    )))
+}
+
 pub fn verify(image: &Volume, drive: &Volume, progress_mutex: &ProgressMutex) -> Result<()> {
     let mut image_file = open_for_reading(image)?;
-    let mut drive_file = OpenOptions::new()
-        .read(true)
-        .custom_flags(libc::O_DIRECT)
-        .open(&drive.path)?;
+    let mut drive_file = open_direct(drive)?;
 
     let mut image_buffer = [0u8; BLOCK_SIZE];
-    let drive_buffer_ptr = unsafe { alloc(Layout::from_size_align(BLOCK_SIZE, 4096)?) };
-    let drive_buffer = unsafe { std::slice::from_raw_parts_mut(drive_buffer_ptr, BLOCK_SIZE) };
+    let mut drive_buffer = vec![0u8; BLOCK_SIZE + DIRECT_ALIGN];
+    let drive_buffer = aligned(&mut drive_buffer, BLOCK_SIZE);
 
-    let timer = Instant::now();
+    progress_mutex.start();
 
     loop {
-        let len = match image_file.read_exact(&mut image_buffer) {
-            Ok(_) => BLOCK_SIZE,
-            Err(_) => image_file.read(&mut image_buffer)?,
-        };
+        let offset = progress_mutex.done();
+        let len = read_full(&mut image_file, &mut image_buffer)
+            .map_err(|err| failure(Phase::Read, &image.path, offset, codec_error(image, err)))?;
 
         if len == 0 {
             break;
         }
 
-        let _ = drive_file.read(drive_buffer)?;
+        let _ = drive_file
+            .read(drive_buffer)
+            .map_err(|err| failure(Phase::Verify, &drive.path, offset, err))?;
 
         if image_buffer[..len] != drive_buffer[..len] {
-            return Err(anyhow!(io::Error::other("Verification failed")));
+            return Err(anyhow!(io::Error::other(format!(
+                "Verification failed at offset {offset} ({}), the drive holds different data",
+                humanize(offset)
+            ))));
+        }
+
+        let mut progress = progress_mutex.checkpoint(len);
+
+        if progress.cancelled {
+            progress.finished = true;
+            return Ok(());
+        }
+    }
+
+    let mut progress = progress_mutex.lock().unwrap();
+
+    if progress.size > 0 && progress.done != progress.size {
+        return Err(anyhow!(io::Error::other(format!(
+            "Verification failed, the image holds {} bytes instead of {}",
+            progress.done, progress.size
+        ))));
+    }
+
+    let elapsed = progress.elapsed();
+    progress.time += elapsed;
+    progress.finished = true;
+
+    Ok(())
+}
+
+// Decoders only report a truncated stream or a bad trailer checksum as a read
+// error, tell those apart from a plain mismatch. Errors of the underlying file
+// keep their errno for `explain::hint`. zstd reports corrupt frames as `Other`.
+fn codec_error(image: &Volume, err: io::Error) -> io::Error {
+    let decoder_error = err.raw_os_error().is_none()
+        && matches!(
+            err.kind(),
+            io::ErrorKind::InvalidData
+                | io::ErrorKind::InvalidInput
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::Other
+        );
+    if image.compression == Compression::None || !decoder_error {
+        return err;
+    }
+
+    io::Error::new(
+        err.kind(),
+        format!("The compressed image is corrupted or truncated: {err}"),
+    )
+}
+
+// O_DIRECT reads need a buffer aligned to the logical block size,
+// 4096 covers every drive.
+const DIRECT_ALIGN: usize = 4096;
+
+fn aligned(buffer: &mut [u8], len: usize) -> &mut [u8] {
+    let start = buffer.as_ptr().align_offset(DIRECT_ALIGN);
+    &mut buffer[start..start + len]
+}
+
+fn open_direct(drive: &Volume) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(&drive.path)
+        .map_err(|err| failure(Phase::Open, &drive.path, 0, err))
+}
+
+fn read_full(file: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(len)
+}
+
+// Picks the first and the last block plus `samples` random ones.
+fn sample_blocks(size: u64, samples: u64) -> BTreeSet<u64> {
+    let blocks = size.div_ceil(BLOCK_SIZE as u64);
+    let mut sampled = BTreeSet::from([0, blocks.saturating_sub(1)]);
+
+    while (sampled.len() as u64) < (samples + 2).min(blocks) {
+        sampled.insert(fastrand::u64(0..blocks));
+    }
+
+    sampled
+}
+
+pub fn verify_sampled(
+    image: &Volume,
+    drive: &Volume,
+    size: u64,
+    samples: u64,
+    progress_mutex: &ProgressMutex,
+) -> Result<()> {
+    let drive_file = open_direct(drive)?;
+
+    // Uncompressed images are read at sampled offsets only, compressed ones
+    // have to be decompressed up to each of them.
+    let seekable_image = match image.compression {
+        Compression::None => {
+            Some(File::open(&image.path).map_err(|err| failure(Phase::Open, &image.path, 0, err))?)
+        }
+        _ => None,
+    };
+    let mut image_file = open_for_reading(image)?;
+
+    let mut image_buffer = vec![0u8; BLOCK_SIZE];
+    let mut drive_buffer = vec![0u8; BLOCK_SIZE + DIRECT_ALIGN];
+    let drive_buffer = aligned(&mut drive_buffer, BLOCK_SIZE);
+
+    let blocks = sample_blocks(size, samples);
+    progress_mutex.lock().unwrap().size = blocks
+        .iter()
+        .map(|block| (size - block * BLOCK_SIZE as u64).min(BLOCK_SIZE as u64))
+        .sum();
+
+    progress_mutex.start();
+    let mut position = 0;
+
+    for block in blocks {
+        let offset = block * BLOCK_SIZE as u64;
+        let len = (size - offset).min(BLOCK_SIZE as u64) as usize;
+
+        let image_len = match &seekable_image {
+            Some(seekable_image) => seekable_image
+                .read_at(&mut image_buffer[..len], offset)
+                .map_err(|err| failure(Phase::Read, &image.path, offset, err))?,
+            None => {
+                while position < block {
+                    let len = read_full(&mut image_file, &mut image_buffer).map_err(|err| {
+                        let offset = position * BLOCK_SIZE as u64;
+                        failure(Phase::Read, &image.path, offset, codec_error(image, err))
+                    })?;
+                    position += 1;
+
+                    let mut progress = progress_mutex.skipped(len);
+                    if progress.cancelled {
+                        progress.finished = true;
+                        return Ok(());
+                    }
+                }
+                position += 1;
+                read_full(&mut image_file, &mut image_buffer[..len]).map_err(|err| {
+                    failure(Phase::Read, &image.path, offset, codec_error(image, err))
+                })?
+            }
+        };
+
+        let drive_len = drive_file
+            .read_at(drive_buffer, offset)
+            .map_err(|err| failure(Phase::Verify, &drive.path, offset, err))?;
+
+        if image_len < len || drive_len < len || image_buffer[..len] != drive_buffer[..len] {
+            return Err(anyhow!(io::Error::other(format!(
+                "Sampled verification failed at offset {offset} ({}), the drive holds different data",
+                humanize(offset)
+            ))));
         }
 
-        let mut progress = progress_mutex.lock().unwrap();
-        progress.done += len as u64;
+        let mut progress = progress_mutex.checkpoint(len);
+
+        if progress.cancelled {
+            progress.finished = true;
+            return Ok(());
+        }
     }
 
     let mut progress = progress_mutex.lock().unwrap();
-    progress.secs += timer.elapsed().as_secs();
+    let elapsed = progress.elapsed();
+    progress.time += elapsed;
+    progress.finished = true;
+
+    Ok(())
+}
+
+pub fn verify_manifest(
+    manifest: &Manifest,
+    drive: &Volume,
+    progress_mutex: &ProgressMutex,
+) -> Result<()> {
+    if drive.size.is_some_and(|size| manifest.size > size) {
+        return Err(anyhow!(io::Error::other(
+            "The manifest describes more data than the drive can hold"
+        )));
+    }
+
+    let mut drive_file = open_direct(drive)?;
+
+    let block_size = manifest.block_size as usize;
+    let mut drive_buffer = vec![0u8; block_size + DIRECT_ALIGN];
+    let drive_buffer = aligned(&mut drive_buffer, block_size);
+
+    progress_mutex.start();
+
+    for (offset, digest) in &manifest.digests {
+        let len = (manifest.size - offset).min(manifest.block_size) as usize;
+
+        drive_file
+            .seek(SeekFrom::Start(*offset))
+            .and_then(|_| drive_file.read(drive_buffer))
+            .map_err(|err| failure(Phase::Verify, &drive.path, *offset, err))?;
+
+        if manifest::digest(&drive_buffer[..len]) != *digest {
+            return Err(anyhow!(io::Error::other(format!(
+                "Verification failed at offset {offset} ({}), the drive holds different data",
+                humanize(*offset)
+            ))));
+        }
+
+        let mut progress = progress_mutex.checkpoint(len);
+
+        if progress.cancelled {
+            progress.finished = true;
+            return Ok(());
+        }
+    }
+
+    let mut progress = progress_mutex.lock().unwrap();
+    progress.time = progress.elapsed();
     progress.finished = true;
 
     Ok(())
@@ -230,3 +1100,26 @@ pub fn humanize(size: u64) -> String {
         format!("{:.1} {}", s as f64 + f as f64 / 1024.0, sfx[i])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codec_errors() {
+        let image = Volume {
+            vtype: VolumeType::Image,
+            path: OsString::from("disk.img.xz"),
+            size: None,
+            compression: Compression::Xz,
+            identity: None,
+        };
+
+        let corrupt = codec_error(&image, io::Error::new(io::ErrorKind::InvalidData, "bad"));
+        assert!(corrupt.to_string().contains("corrupted or truncated"));
+
+        let eio = codec_error(&image, io::Error::from_raw_os_error(libc::EIO));
+        assert_eq!(eio.raw_os_error(), Some(libc::EIO));
+        assert!(!eio.to_string().contains("corrupted"));
+    }
+}
@@ -2,17 +2,34 @@
 //  License, v. 2.0. If a copy of the MPL was not distributed with this
 //  file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod checksum;
+mod config;
+mod explain;
+mod header;
+mod hotplug;
 mod imge;
 mod mainloop;
+mod manifest;
+mod probe;
+mod signature;
+mod sysfs;
+mod watchdog;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use argp::FromArgs;
 use crossterm::terminal;
 use mainloop::Mainloop;
-use std::ffi::OsString;
-use std::fs::{remove_file, File};
-use std::io;
+use signal_hook::consts::TERM_SIGNALS;
+use signal_hook::flag;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, remove_file, File};
+use std::io::{self, IsTerminal, Write};
+use std::panic;
 use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
 
 #[derive(Clone, Default, FromArgs)]
 /// Write disk images to physical drive or vice versa.
@@ -21,23 +38,134 @@ struct Args {
     #[argp(switch, short = 'a')]
     all_drives: bool,
 
-    /// use this drive, do not ask
+    /// expected checksum of the image (hex digest or checksum file)
+    #[argp(option, short = 'c')]
+    checksum: Option<OsString>,
+
+    /// ask to type the drive name for drives larger than this (GiB)
+    #[argp(option, default = "256")]
+    confirm_size: u64,
+
+    /// use this drive (path, by-id link or serial), do not ask
     #[argp(option, short = 'd')]
     drive: Option<OsString>,
 
+    /// offer only drives allowed by the configuration
+    #[argp(switch)]
+    factory: bool,
+
+    /// allow writing to the drive holding the running system
+    #[argp(switch)]
+    force_system_drive: bool,
+
     /// copy drive to image (instead of image to drive)
     #[argp(switch, short = 'f')]
     from_drive: bool,
 
+    /// keep the partial image when copying from a drive is cancelled
+    #[argp(switch)]
+    keep_partial: bool,
+
+    /// write a hash manifest of the copied data to this file
+    #[argp(option, short = 'm')]
+    manifest: Option<OsString>,
+
+    /// replace an existing image when copying from a drive
+    #[argp(switch)]
+    overwrite: bool,
+
+    /// refuse images not signed with this minisign or OpenPGP public key (base64 or file)
+    #[argp(option, short = 'k')]
+    public_key: Option<OsString>,
+
+    /// unmount all partitions of the drive before writing
+    #[argp(switch, short = 'u')]
+    unmount: bool,
+
+    /// verify only the first and last MiB and this many random MiB blocks
+    #[argp(option, short = 's')]
+    sample: Option<u64>,
+
+    /// give up when no data was transferred for this many seconds (0 waits forever)
+    #[argp(option, default = "30")]
+    stall_timeout: u64,
+
     /// verify if data was copied correctly
     #[argp(switch, short = 'v')]
     verify: bool,
 
+    /// treat image as a hash manifest and only verify the drive against it
+    #[argp(switch, short = 'V')]
+    verify_manifest: bool,
+
     /// path to image
     #[argp(positional)]
     image: OsString,
 }
 
+#[derive(FromArgs)]
+/// Write a header backup taken before copying back to its drive.
+struct RestoreHeader {
+    /// do not ask for confirmation
+    #[argp(switch, short = 'y')]
+    yes: bool,
+
+    /// header backup from ~/.local/state/imge
+    #[argp(positional)]
+    backup: OsString,
+
+    /// drive to restore (the drive the backup was taken from by default)
+    #[argp(positional)]
+    drive: Option<OsString>,
+}
+
+fn ask(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn restore_header() -> Result<()> {
+    let args: Vec<OsString> = env::args_os().skip(2).collect();
+    let args =
+        RestoreHeader::from_args(&["imge restore-header"], &args).unwrap_or_else(|early_exit| {
+            std::process::exit(match early_exit {
+                argp::EarlyExit::Help(help) => {
+                    println!("{}", help.generate(argp::DEFAULT));
+                    0
+                }
+                argp::EarlyExit::Err(err) => {
+                    eprintln!("{err}\nRun imge restore-header --help for more information.");
+                    1
+                }
+            })
+        });
+
+    let backup = header::read(&args.backup)?;
+    let drive = args.drive.unwrap_or(OsString::from(&backup.device));
+
+    println!(
+        "Restoring the header of {} {} ({}) saved on {} to {}.",
+        backup.identity.model,
+        backup.identity.serial,
+        imge::humanize(backup.identity.size),
+        backup.created,
+        drive.to_string_lossy()
+    );
+
+    if !args.yes && !ask("Continue?")? {
+        return Ok(());
+    }
+
+    header::restore(&backup, &drive)?;
+    println!("Header restored.");
+
+    Ok(())
+}
+
 fn terminal_raw_mode(raw_mode: bool) -> Result<()> {
     if raw_mode {
         crossterm::execute!(io::stdout(), terminal::EnterAlternateScreen)?;
@@ -50,27 +178,117 @@ fn terminal_raw_mode(raw_mode: bool) -> Result<()> {
     Ok(())
 }
 
+// Leaves raw mode and the alternate screen before the panic message is
+// printed, so it ends up readable in the normal terminal. Panics of worker
+// threads are shown in the error dialog instead of over the screen.
+fn restore_terminal_on_panic() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().name() == Some("main") {
+            let _ = terminal_raw_mode(false);
+            default_hook(info);
+        }
+    }));
+}
+
+// The first SIGTERM, SIGINT or SIGQUIT cancels the running phase and exits
+// cleanly, a second one exits at once in case the drive does not respond.
+fn termination_flag() -> Result<Arc<AtomicBool>> {
+    let terminated = Arc::new(AtomicBool::new(false));
+
+    for signal in TERM_SIGNALS {
+        flag::register_conditional_shutdown(*signal, 1, terminated.clone())?;
+        flag::register(*signal, terminated.clone())?;
+    }
+
+    Ok(terminated)
+}
+
 fn main() -> Result<()> {
-    let args: Args = argp::parse_args_or_exit(argp::DEFAULT);
-
-    if args.from_drive {
-        let path = Path::new(&args.image);
-        let dirname = path.parent().unwrap().to_string_lossy();
-        let filename = path.file_name().unwrap().to_string_lossy();
-        let write_test = if dirname.is_empty() {
-            format!(".{filename}.imge")
-        } else {
-            format!("{dirname}/.{filename}.imge")
-        };
+    if env::args_os().nth(1).as_deref() == Some(OsStr::new("restore-header")) {
+        return restore_header();
+    }
+
+    let mut args: Args = argp::parse_args_or_exit(argp::DEFAULT);
+    let config = config::load()?;
+    let mut checksum = None;
+    let mut signature = None;
+    let mut manifest = None;
+
+    if args.verify_manifest {
+        if args.from_drive
+            || args.checksum.is_some()
+            || args.public_key.is_some()
+            || args.sample.is_some()
+        {
+            return Err(anyhow!(
+                "--verify-manifest cannot be used with --from-drive, --checksum, --public-key or --sample"
+            ));
+        }
+        manifest = Some(manifest::read(&args.image)?);
+    } else if args.from_drive {
+        if args.checksum.is_some() {
+            return Err(anyhow!("--checksum cannot be used with --from-drive"));
+        }
+        if args.public_key.is_some() {
+            return Err(anyhow!("--public-key cannot be used with --from-drive"));
+        }
+
+        if !args.overwrite && fs::metadata(&args.image).is_ok_and(|m| m.is_file()) {
+            let question = format!(
+                "{} already exists. Overwrite it?",
+                args.image.to_string_lossy()
+            );
+            if !io::stdin().is_terminal() || !ask(&question)? {
+                return Err(anyhow!(
+                    "{} already exists, use --overwrite to replace it",
+                    args.image.to_string_lossy()
+                ));
+            }
+        }
+
+        let write_test = imge::temporary_path(&args.image);
         File::create(&write_test)?;
         remove_file(&write_test)?;
     } else {
-        File::open(&args.image)?;
+        let file = File::open(&args.image)?;
+        if file.metadata()?.is_file() {
+            checksum = checksum::find(Path::new(&args.image), args.checksum.as_deref())?;
+        }
+        if config.public_key_enforced
+            && let (Some(arg), Some(key)) = (&args.public_key, &config.public_key)
+            && arg != key.as_str()
+        {
+            return Err(anyhow!(
+                "--public-key cannot replace the public key set in {}",
+                config::SYSTEM_CONFIG
+            ));
+        }
+        let public_key = args
+            .public_key
+            .clone()
+            .or(config.public_key.clone().map(OsString::from));
+        if let Some(public_key) = &public_key {
+            signature = Some(signature::find(Path::new(&args.image), public_key)?);
+        }
+    }
+
+    if let Some(drive) = &args.drive {
+        args.drive = Some(imge::resolve_drive(
+            drive,
+            args.all_drives,
+            args.factory,
+            &config,
+        )?);
     }
 
+    let terminated = termination_flag()?;
+    restore_terminal_on_panic();
+
     terminal_raw_mode(true)?;
-    Mainloop::new(args).run()?;
+    let result = Mainloop::new(args, config, checksum, signature, manifest).run(&terminated);
     terminal_raw_mode(false)?;
 
-    Ok(())
+    result
 }
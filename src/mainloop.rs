@@ -2,29 +2,45 @@
 //  License, v. 2.0. If a copy of the MPL was not distributed with this
 //  file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use crate::checksum::Checksum;
+use crate::config::Config;
+use crate::explain;
+use crate::header;
+use crate::hotplug::Hotplug;
 use crate::imge;
+use crate::manifest::Manifest;
+use crate::signature::Signature;
+use crate::watchdog::Watchdog;
 use crate::Args;
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use derivative::Derivative;
 use num_format::{SystemLocale, ToFormattedString};
 use ratatui::prelude::*;
 use ratatui::widgets::*;
+use std::any::Any;
 use std::ffi::OsString;
 use std::os::unix::fs::FileTypeExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use std::{fs, io};
 
-#[derive(Default, PartialEq)]
+#[derive(Clone, Copy, Default, PartialEq)]
 enum Modal {
     #[default]
     None,
     Keybindings,
     Warning,
+    Confirm,
+    Mounted,
+    Validating,
     Copying,
     Verifying,
+    Cancel,
+    Cancelled,
     Victory,
     Error,
 }
@@ -33,21 +49,47 @@ enum Modal {
 #[derivative(Default)]
 pub struct Mainloop {
     args: Args,
+    config: Config,
     ui_accent: Style,
     image_basename: String,
+    image_target: OsString,
     image_compression: imge::Compression,
+    checksum: Option<Arc<Checksum>>,
+    signature: Option<Arc<Signature>>,
+    manifest: Option<Arc<Manifest>>,
     drives: Vec<imge::Drive>,
+    hotplug: Hotplug,
+    drives_changed: bool,
+    highlighted: Option<OsString>,
+    expanded: Vec<OsString>,
+    show_details: bool,
     selected_row: usize,
     selected_drive: Option<OsString>,
     selected_size: u64,
+    pinned: Option<imge::DriveIdentity>,
     modal: Modal,
+    resume: Modal,
+    confirmation: String,
+    confirmed: bool,
+    // <u> was pressed for this attempt, -u stays in `args.unmount`.
+    unmount_confirmed: bool,
+    unmounted: Vec<String>,
+    header_backup: Arc<Mutex<Option<PathBuf>>>,
     progress: Option<imge::ProgressMutex>,
+    watchdog: Option<Watchdog>,
+    worker: Option<JoinHandle<()>>,
     error: Arc<Mutex<Option<Error>>>,
     exit: bool,
 }
 
 impl Mainloop {
-    pub fn new(args: Args) -> Self {
+    pub fn new(
+        args: Args,
+        config: Config,
+        checksum: Option<Checksum>,
+        signature: Option<Signature>,
+        manifest: Option<Manifest>,
+    ) -> Self {
         let ui_accent = match args.from_drive {
             false => Style::new().magenta(),
             true => Style::new().yellow(),
@@ -73,33 +115,111 @@ impl Mainloop {
             _ => imge::Compression::None,
         };
 
+        // Drive-to-image copies go to a temporary file first,
+        // unless the image is a device such as /dev/null.
+        let image_target = match fs::metadata(&args.image) {
+            Ok(metadata) if !metadata.is_file() => args.image.clone(),
+            _ if args.from_drive => imge::temporary_path(&args.image),
+            _ => args.image.clone(),
+        };
+
         Self {
             args: args.clone(),
+            config,
             ui_accent,
             image_basename,
+            image_target,
             image_compression,
+            checksum: checksum.map(Arc::new),
+            signature: signature.map(Arc::new),
+            manifest: manifest.map(Arc::new),
             selected_drive: args.drive,
             ..Default::default()
         }
     }
 
-    pub fn run(&mut self) -> Result<()> {
+    /// Runs the UI until it is closed, or until `terminated` is set by
+    /// a signal handler, which cancels the running phase.
+    pub fn run(&mut self, terminated: &AtomicBool) -> Result<()> {
         let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
 
         self.update_drives(true)?;
 
         if self.args.drive.is_some() {
-            self.start_copying();
+            self.pinned = self.selected().map(imge::Drive::identity);
+
+            if let Err(err) = self.check_target() {
+                *self.error.lock().unwrap() = Some(err);
+            } else if self.held_by().is_some() {
+                self.modal = Modal::Warning;
+            } else {
+                self.proceed();
+            }
         }
 
         while !self.exit {
+            if terminated.load(Ordering::Relaxed) {
+                break;
+            }
+
+            if self.hotplug.changed() {
+                self.drives_changed = true;
+            }
+
+            // Only refresh behind the list, not while a drive is being
+            // confirmed or written.
+            if self.drives_changed
+                && self.args.drive.is_none()
+                && matches!(self.modal, Modal::None | Modal::Keybindings)
+            {
+                self.drives_changed = false;
+                self.refresh_drives()?;
+            }
+
+            // A worker blocked on a pulled or hung drive may never return,
+            // it is left behind instead of being joined.
+            if let (Some(watchdog), Some(progress)) = (&mut self.watchdog, &self.progress)
+                && matches!(
+                    self.modal,
+                    Modal::Validating | Modal::Copying | Modal::Verifying | Modal::Cancel
+                )
+                && let Err(err) = watchdog.check(&progress.lock().unwrap())
+            {
+                progress.cancel();
+                self.worker = None;
+                self.watchdog = None;
+                *self.error.lock().unwrap() = Some(err);
+            }
+
+            // A worker that panicked never marks its progress finished.
+            if self.error.lock().unwrap().is_none()
+                && self.worker.as_ref().is_some_and(JoinHandle::is_finished)
+                && self
+                    .progress
+                    .as_ref()
+                    .is_some_and(|progress| !progress.lock().unwrap().finished)
+                && let Err(payload) = self.worker.take().unwrap().join()
+            {
+                self.watchdog = None;
+                *self.error.lock().unwrap() = Some(worker_panic(payload));
+            }
+
             if self.error.lock().unwrap().is_some() {
                 self.modal = Modal::Error;
             } else if let Some(progress) = &self.progress
+                && self.modal != Modal::Cancel
                 && progress.lock().unwrap().finished
             {
-                if self.args.verify && self.modal == Modal::Copying {
+                if progress.lock().unwrap().cancelled {
+                    self.finish_cancel();
+                } else if self.modal == Modal::Validating {
+                    self.start_copying();
+                } else if (self.args.verify || self.args.sample.is_some())
+                    && self.modal == Modal::Copying
+                {
                     self.start_verifying()?;
+                } else if let Err(err) = self.rename_image() {
+                    *self.error.lock().unwrap() = Some(err);
                 } else if self.args.drive.is_none() {
                     self.modal = Modal::Victory;
                 } else {
@@ -113,8 +233,13 @@ impl Mainloop {
                 match self.modal {
                     Modal::Keybindings => self.render_keybindings(frame),
                     Modal::Warning => self.render_warning(frame),
+                    Modal::Confirm => self.render_confirm(frame),
+                    Modal::Mounted => self.render_mounted(frame),
+                    Modal::Validating => self.render_validating(frame),
                     Modal::Copying => self.render_copying(frame).unwrap(),
-                    Modal::Verifying => self.render_verifying(frame),
+                    Modal::Verifying => self.render_verifying(frame).unwrap(),
+                    Modal::Cancel => self.render_cancel(frame),
+                    Modal::Cancelled => self.render_cancelled(frame),
                     Modal::Victory => self.render_victory(frame),
                     Modal::Error => self.render_error(frame),
                     _ => {}
@@ -129,11 +254,45 @@ impl Mainloop {
             }
         }
 
+        let interrupted = self
+            .progress
+            .as_ref()
+            .is_some_and(|progress| !progress.lock().unwrap().finished)
+            && self.error.lock().unwrap().is_none();
+        let stopped = self.stop_worker();
+
+        // Ctrl-C and signals end a running phase like a confirmed cancel.
+        if interrupted && stopped.is_ok() {
+            if self.modal != Modal::Cancel {
+                self.resume = self.modal;
+            }
+            self.finish_cancel();
+        }
+
+        // Leftover of a failed or interrupted drive-to-image copy.
+        if self.image_target != self.args.image {
+            let _ = fs::remove_file(&self.image_target);
+        }
+
+        stopped?;
+
+        if terminated.load(Ordering::Relaxed) {
+            return Err(anyhow!(match interrupted {
+                true => "Terminated by a signal, the transfer was cancelled",
+                false => "Terminated by a signal",
+            }));
+        }
+
         Ok(())
     }
 
     fn render_window(&self, frame: &mut Frame) {
         let header = match self.args.from_drive {
+            _ if self.manifest.is_some() => Line::from(vec![
+                "Select the drive you wanna verify against ".into(),
+                Span::styled(&self.image_basename, self.ui_accent),
+                ".".into(),
+            ]),
             false => Line::from(vec![
                 "Select the drive you wanna copy ".into(),
                 Span::styled(&self.image_basename, self.ui_accent),
@@ -146,9 +305,16 @@ impl Mainloop {
             ]),
         };
 
-        let p = Paragraph::new(vec![header])
-            .wrap(Wrap { trim: true })
-            .centered();
+        let mut lines = vec![header];
+        if !self.unmounted.is_empty() {
+            lines.push(Line::from(vec![
+                "Unmounted ".into(),
+                Span::styled(self.unmounted.join(", "), self.ui_accent),
+                ".".into(),
+            ]));
+        }
+
+        let p = Paragraph::new(lines).wrap(Wrap { trim: true }).centered();
         frame.render_widget(p, frame.area());
 
         if self.modal == Modal::None || self.modal == Modal::Keybindings {
@@ -157,7 +323,13 @@ impl Mainloop {
                 Span::styled("<i>", self.ui_accent),
                 " to display keybindings.".into(),
             ]);
-            let area = Rect::new(0, frame.area().height - 1, frame.area().width, 1);
+            let area = Rect::new(
+                0,
+                frame.area().height.saturating_sub(1),
+                frame.area().width,
+                1,
+            )
+            .intersection(frame.area());
             frame.render_widget(info, area);
         }
     }
@@ -165,7 +337,13 @@ impl Mainloop {
     fn render_drives(&self, frame: &mut Frame) {
         let mut rows = Vec::with_capacity(self.drives.len());
 
-        for drive in &self.drives {
+        let mut selected_row = 0;
+
+        for (i, drive) in self.drives.iter().enumerate() {
+            if i == self.selected_row {
+                selected_row = rows.len();
+            }
+
             let mut row = Vec::with_capacity(6);
 
             row.push(Cell::from(drive.name.to_string_lossy()));
@@ -182,7 +360,13 @@ impl Mainloop {
                 row.push(Cell::from(is_removable));
             }
             if frame.area().width > 120 {
-                let is_mounted = if drive.is_mounted {
+                let is_mounted = if drive.is_locked {
+                    "Locked"
+                } else if drive.details.is_read_only {
+                    "Read-only"
+                } else if drive.is_system {
+                    "System"
+                } else if drive.is_mounted {
                     "Mounted"
                 } else {
                     "Unmounted"
@@ -192,7 +376,23 @@ impl Mainloop {
             let size = imge::humanize(drive.size);
             row.push(Cell::from(Text::from(size).right_aligned()));
 
-            rows.push(Row::new(row));
+            if drive.is_locked || (drive.details.is_read_only && !self.args.from_drive) {
+                rows.push(Row::new(row).dark_gray());
+            } else if self.highlighted.as_ref() == Some(&drive.name) {
+                rows.push(Row::new(row).bold().green());
+            } else {
+                rows.push(Row::new(row));
+            }
+
+            if self.expanded.contains(&drive.name) {
+                for (j, partition) in drive.partitions.iter().enumerate() {
+                    rows.push(self.partition_row(
+                        frame,
+                        partition,
+                        j + 1 == drive.partitions.len(),
+                    ));
+                }
+            }
         }
 
         let mut widths: Vec<Constraint> = Vec::with_capacity(self.drives.len());
@@ -215,12 +415,117 @@ impl Mainloop {
             .row_highlight_style(self.ui_accent);
 
         let mut state = TableState::default();
-        state.select(Some(self.selected_row));
+        state.select(Some(selected_row));
+
+        let mut area = Rect::new(
+            0,
+            3,
+            frame.area().width,
+            frame.area().height.saturating_sub(3),
+        )
+        .intersection(frame.area());
+
+        if self.show_details
+            && let Some(drive) = self.selected()
+        {
+            let lines = self.details_lines(drive);
+            let height = (lines.len() as u16 + 2).min(area.height / 2);
+            let [table_area, details_area] =
+                Layout::vertical([Constraint::Min(0), Constraint::Length(height)]).areas(area);
+            area = table_area;
+
+            let block = Block::default()
+                .title_top(" Details ")
+                .title_style(Style::new().add_modifier(Modifier::BOLD))
+                .borders(Borders::TOP)
+                .border_style(Style::new().dark_gray());
+
+            frame.render_widget(Paragraph::new(lines).block(block), details_area);
+        }
 
-        let area = Rect::new(0, 3, frame.area().width, frame.area().height - 3);
         frame.render_stateful_widget(table, area, &mut state);
     }
 
+    fn details_lines(&self, drive: &imge::Drive) -> Vec<Line<'_>> {
+        let details = &drive.details;
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        let field = |name: &'static str, value: String| {
+            Line::from(vec![
+                Span::styled(format!(" {name:<12}"), Style::new().dark_gray()),
+                Span::raw(value),
+            ])
+        };
+
+        let mut transport = details.transport.clone();
+        if !details.usb_path.is_empty() {
+            transport.push_str(&format!(", port {}", details.usb_path));
+        }
+
+        let mut lines = vec![
+            field("Transport", transport),
+            field(
+                "Device",
+                [&details.vendor, &drive.model, &details.revision]
+                    .iter()
+                    .filter(|field| !field.is_empty())
+                    .map(|field| field.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", "),
+            ),
+            field("Serial", drive.serial.clone()),
+            field("WWN", drive.wwn.clone()),
+            field(
+                "Sectors",
+                format!(
+                    "{} bytes logical, {} bytes physical",
+                    details.logical_block_size, details.physical_block_size
+                ),
+            ),
+            field(
+                "Flags",
+                format!(
+                    "read-only {}, rotational {}, discard {}",
+                    yes_no(details.is_read_only),
+                    yes_no(details.is_rotational),
+                    yes_no(details.supports_discard)
+                ),
+            ),
+        ];
+
+        for (i, link) in drive.by_id.iter().enumerate() {
+            lines.push(field(if i == 0 { "By-id" } else { "" }, link.clone()));
+        }
+
+        lines
+    }
+
+    fn partition_row(&self, frame: &Frame, partition: &imge::Partition, last: bool) -> Row<'_> {
+        let mut row = Vec::with_capacity(6);
+
+        let branch = if last { "└ " } else { "├ " };
+        row.push(Cell::from(format!(
+            "{branch}{}",
+            partition.name.to_string_lossy()
+        )));
+        row.push(Cell::from(format!(
+            "{} {}",
+            partition.fstype, partition.label
+        )));
+        if frame.area().width > 160 {
+            row.push(Cell::from(partition.uuid.clone()));
+        }
+        if frame.area().width > 80 {
+            row.push(Cell::from(""));
+        }
+        if frame.area().width > 120 {
+            row.push(Cell::from(partition.mountpoint.clone().unwrap_or_default()));
+        }
+        let size = imge::humanize(partition.size);
+        row.push(Cell::from(Text::from(size).right_aligned()));
+
+        Row::new(row).italic()
+    }
+
     fn render_modal(&self, frame: &mut Frame, title: &str, lines: Vec<Line>) {
         let block = Block::default()
             .title_top(title)
@@ -235,11 +540,13 @@ impl Mainloop {
             .centered()
             .block(block);
 
+        // Grows for long messages, such as errors with a hint and a header
+        // backup, `line_count` adds the borders above and below the text.
         let w = 72;
-        let h = 10;
-        let x = (frame.area().width - w) / 2;
-        let y = (frame.area().height - h) / 2;
-        let area = Rect::new(x, y, w, h);
+        let h = (p.line_count(w - 2) as u16).max(10);
+        let x = frame.area().width.saturating_sub(w) / 2;
+        let y = frame.area().height.saturating_sub(h) / 2;
+        let area = Rect::new(x, y, w, h).intersection(frame.area());
 
         frame.render_widget(p, area);
     }
@@ -256,17 +563,21 @@ impl Mainloop {
                 "Refresh drives                   ".into(),
             ]),
             Line::from(vec![
-                Span::styled("<up>     ", self.ui_accent),
-                "Select the drive above           ".into(),
+                Span::styled("<↑/↓>    ", self.ui_accent),
+                "Select the drive above/below     ".into(),
             ]),
             Line::from(vec![
-                Span::styled("<down>   ", self.ui_accent),
-                "Select the drive below           ".into(),
+                Span::styled("<→/←>    ", self.ui_accent),
+                "Show/hide partitions             ".into(),
             ]),
             Line::from(vec![
                 Span::styled("<enter>  ", self.ui_accent),
                 "Write the image to selected drive".into(),
             ]),
+            Line::from(vec![
+                Span::styled("<d>      ", self.ui_accent),
+                "Show/hide drive details          ".into(),
+            ]),
             Line::from(vec![
                 Span::styled("<esc>    ", self.ui_accent),
                 "Quit                             ".into(),
@@ -304,71 +615,232 @@ impl Mainloop {
             "?".into(),
         ]));
 
+        if let Some(holders) = self.held_by() {
+            lines.push(Line::from(vec![
+                "It is in use by ".into(),
+                Span::styled(holders.join(", "), self.ui_accent),
+                ",".into(),
+            ]));
+            lines.push(Line::from(
+                "which would be destroyed, so it will not be written.",
+            ));
+            lines.push(Line::from(""));
+            lines.push(Line::from(vec![
+                Span::styled("<esc> ", self.ui_accent),
+                "Cancel".into(),
+            ]));
+
+            self.render_modal(frame, " Warning ", lines);
+            return;
+        }
+
         if !self.args.from_drive {
             lines.push(Line::from("This is something that cannot be undone."));
+
+            if let Some(line) = self.destroyed_line() {
+                lines.pop();
+                lines.push(line);
+            }
         }
 
-        lines.push(Line::from(""));
         lines.push(Line::from(""));
 
-        lines.push(Line::from(vec![
+        if let Some(signature) = &self.signature {
+            lines.push(Line::from(vec![
+                "Signed by ".into(),
+                Span::styled(signature.signer(), self.ui_accent),
+                ", verified first.".into(),
+            ]));
+        } else if let Some(checksum) = &self.checksum {
+            lines.push(Line::from(vec![
+                "The image will be checked against ".into(),
+                Span::styled(checksum.algorithm.name(), self.ui_accent),
+                " from ".into(),
+                Span::styled(&checksum.source, self.ui_accent),
+                " first.".into(),
+            ]));
+        } else if let Some(warning) = self.space_warning() {
+            lines.push(warning);
+        } else {
+            lines.push(Line::from(""));
+        }
+
+        let mut keys = vec![
             Span::styled("<esc> ", self.ui_accent),
             "Cancel".into(),
             "          ".into(),
             Span::styled("<enter> ", self.ui_accent),
             "Continue".into(),
-        ]));
+        ];
+        if self.offers_unmount() {
+            keys.push("          ".into());
+            keys.push(Span::styled("<u> ", self.ui_accent));
+            keys.push("Unmount first".into());
+        }
+        lines.push(Line::from(keys));
 
         self.render_modal(frame, " Warning ", lines);
     }
 
-    fn render_copying(&self, frame: &mut Frame) -> Result<()> {
-        let progress = self.progress.as_ref().unwrap().lock().unwrap();
-        let area = Rect::new(1, (frame.area().height - 5) / 2, frame.area().width - 2, 5);
+    // Lists what is on the drive, up to three partitions.
+    fn destroyed_line(&self) -> Option<Line<'_>> {
+        let partitions = &self.selected()?.partitions;
+        if partitions.is_empty() {
+            return None;
+        }
 
-        if progress.size > 0 {
-            let block = Block::default()
-                .title_top(" Copying ")
-                .title_style(Style::new().add_modifier(Modifier::BOLD))
-                .title_alignment(Alignment::Center)
-                .borders(Borders::ALL)
-                .border_style(Style::new().dark_gray())
-                .border_type(BorderType::Rounded);
+        let mut spans = vec![];
+        for (i, partition) in partitions.iter().take(3).enumerate() {
+            if i > 0 {
+                spans.push(", ".into());
+            }
 
-            let gauge = Gauge::default()
-                .gauge_style(self.ui_accent)
-                .style(Style::new().bold())
-                .ratio(progress.percents())
-                .label(format!("{:.1} %", progress.percents() * 100.0))
-                .block(block);
+            let name = Path::new(&partition.name)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            spans.push(Span::styled(name, self.ui_accent));
+
+            let description = [partition.fstype.as_str(), partition.label.as_str()]
+                .iter()
+                .filter(|field| !field.is_empty())
+                .copied()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            if !description.is_empty() {
+                spans.push(format!(" ({description})").into());
+            }
+        }
+        if partitions.len() > 3 {
+            spans.push(format!(" and {} more", partitions.len() - 3).into());
+        }
+        spans.push(" will be destroyed.".into());
 
-            frame.render_widget(gauge, area);
-        } else {
-            let locale = SystemLocale::default()?;
-            let copied_bytes = format!(
-                " {} bytes copied ",
-                progress.done.to_formatted_string(&locale)
-            );
+        Some(Line::from(spans))
+    }
 
-            let lines = vec![
-                Line::from(""),
-                Line::from(""),
-                Line::from(""),
-                Line::from(Span::styled(copied_bytes, self.ui_accent)),
-            ];
+    // Compressed images are assumed to shrink to about half the drive.
+    fn space_warning(&self) -> Option<Line<'_>> {
+        if !self.args.from_drive {
+            return None;
+        }
+
+        let dir = Path::new(&self.image_target)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let free = imge::free_space(dir)?;
+        let needed = match self.image_compression {
+            imge::Compression::None => self.selected_size,
+            _ => self.selected_size / 2,
+        };
 
-            self.render_modal(frame, " Copying ", lines);
+        if free >= needed {
+            return None;
         }
 
-        Ok(())
+        Some(Line::from(vec![
+            "Only ".into(),
+            Span::styled(imge::humanize(free), Style::new().red()),
+            " free on ".into(),
+            Span::styled(dir.to_string_lossy().to_string(), self.ui_accent),
+            match self.image_compression {
+                imge::Compression::None => ", the image needs ".into(),
+                _ => ", the image may need about ".into(),
+            },
+            Span::styled(imge::humanize(needed), self.ui_accent),
+            ".".into(),
+        ]))
     }
 
-    fn render_verifying(&self, frame: &mut Frame) {
+    fn render_confirm(&self, frame: &mut Frame) {
+        let drive = self.selected().unwrap();
+        let drive_name = Path::new(&drive.name)
+            .file_name()
+            .unwrap()
+            .to_string_lossy();
+
+        let reason = if !drive.is_removable {
+            String::from("This is a non-removable drive.")
+        } else {
+            format!("This drive is larger than {} GiB.", self.args.confirm_size)
+        };
+
+        let lines = vec![
+            Line::from(""),
+            Line::from(Span::styled(
+                format!(
+                    "{}  {}  {}",
+                    drive.model,
+                    drive.serial,
+                    imge::humanize(drive.size)
+                ),
+                self.ui_accent,
+            )),
+            Line::from(reason),
+            Line::from(vec![
+                "Type ".into(),
+                Span::styled(drive_name, self.ui_accent),
+                " or the last four characters of its serial.".into(),
+            ]),
+            Line::from(""),
+            Line::from(format!("> {}_", self.confirmation)),
+            Line::from(""),
+            Line::from(vec![
+                Span::styled("<esc> ", self.ui_accent),
+                "Cancel".into(),
+                "          ".into(),
+                Span::styled("<enter> ", self.ui_accent),
+                "Continue".into(),
+            ]),
+        ];
+
+        self.render_modal(frame, " Confirm ", lines);
+    }
+
+    fn render_mounted(&self, frame: &mut Frame) {
+        let drive_path = self
+            .selected_drive
+            .clone()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+
+        let lines = vec![
+            Line::from(""),
+            Line::from(vec![
+                Span::styled(drive_path, self.ui_accent),
+                " has mounted partitions.".into(),
+            ]),
+            Line::from("It is opened exclusively and cannot be written while they are mounted."),
+            Line::from(""),
+            Line::from(""),
+            Line::from(""),
+            Line::from(vec![
+                Span::styled("<esc> ", self.ui_accent),
+                "Cancel".into(),
+                "          ".into(),
+                Span::styled("<u> ", self.ui_accent),
+                "Unmount and write".into(),
+            ]),
+        ];
+
+        self.render_modal(frame, " Mounted ", lines);
+    }
+
+    fn render_validating(&self, frame: &mut Frame) {
         let progress = self.progress.as_ref().unwrap().lock().unwrap();
-        let area = Rect::new(1, (frame.area().height - 5) / 2, frame.area().width - 2, 5);
+        let area = Rect::new(
+            1,
+            frame.area().height.saturating_sub(5) / 2,
+            frame.area().width.saturating_sub(2),
+            5,
+        )
+        .intersection(frame.area());
 
         let block = Block::default()
-            .title_top(" Verifying ")
+            .title_top(" Validating ")
             .title_style(Style::new().add_modifier(Modifier::BOLD))
             .title_alignment(Alignment::Center)
             .borders(Borders::ALL)
@@ -376,7 +848,7 @@ impl Mainloop {
             .border_type(BorderType::Rounded);
 
         let gauge = Gauge::default()
-            .gauge_style(Style::new().blue())
+            .gauge_style(Style::new().green())
             .style(Style::new().bold())
             .ratio(progress.percents())
             .label(format!("{:.1} %", progress.percents() * 100.0))
@@ -385,26 +857,246 @@ impl Mainloop {
         frame.render_widget(gauge, area);
     }
 
-    fn render_victory(&self, frame: &mut Frame) {
+    fn render_copying(&self, frame: &mut Frame) -> Result<()> {
+        let progress = self.progress.as_ref().unwrap().lock().unwrap();
+        self.render_transfer(frame, " Copying ", self.ui_accent, &progress)
+    }
+
+    fn render_verifying(&self, frame: &mut Frame) -> Result<()> {
         let progress = self.progress.as_ref().unwrap().lock().unwrap();
 
-        let speed = if progress.secs > 0 {
-            progress.done / progress.secs
+        let title = match self.args.sample {
+            Some(_) => " Verifying (sampled) ",
+            None => " Verifying ",
+        };
+
+        self.render_transfer(frame, title, Style::new().blue(), &progress)
+    }
+
+    fn render_transfer(
+        &self,
+        frame: &mut Frame,
+        title: &str,
+        accent: Style,
+        progress: &imge::Progress,
+    ) -> Result<()> {
+        let area = Rect::new(
+            1,
+            frame.area().height.saturating_sub(12) / 2,
+            frame.area().width.saturating_sub(2),
+            12,
+        )
+        .intersection(frame.area());
+
+        let block = Block::default()
+            .title_top(title)
+            .title_style(Style::new().add_modifier(Modifier::BOLD))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_style(Style::new().dark_gray())
+            .border_type(BorderType::Rounded);
+
+        let inner = block.inner(area).inner(Margin::new(1, 0));
+        let [gauge_area, stats_area, _, graph_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(0),
+        ])
+        .areas(inner);
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
+
+        let accent = match progress.paused {
+            false => accent,
+            true => Style::new().dark_gray(),
+        };
+
+        if progress.size > 0 {
+            let gauge = Gauge::default()
+                .gauge_style(accent)
+                .style(Style::new().bold())
+                .ratio(progress.percents())
+                .label(gauge_label(progress));
+
+            frame.render_widget(gauge, gauge_area);
         } else {
-            progress.done
+            let locale = SystemLocale::default()?;
+            let lines = vec![
+                Line::from(""),
+                Line::from(Span::styled(
+                    format!(
+                        "{} bytes copied",
+                        progress.done.to_formatted_string(&locale)
+                    ),
+                    accent,
+                )),
+                match progress.paused {
+                    false if progress.flushing => Line::from(FLUSHING_LABEL),
+                    false => Line::from(""),
+                    true => Line::from(paused_label(progress)),
+                },
+            ];
+
+            frame.render_widget(Paragraph::new(lines).centered(), gauge_area);
+        }
+
+        let mut stats = vec![
+            Span::styled(
+                format!("{}/s", imge::humanize(progress.current_speed())),
+                self.ui_accent,
+            ),
+            " now, ".into(),
+            Span::styled(
+                format!("{}/s", imge::humanize(progress.average_speed())),
+                self.ui_accent,
+            ),
+            " average, ".into(),
+            Span::styled(clock(progress.elapsed()), self.ui_accent),
+            " elapsed".into(),
+        ];
+        if let Some(eta) = progress.eta() {
+            stats.push(", ".into());
+            stats.push(Span::styled(clock(eta), self.ui_accent));
+            stats.push(" left".into());
+        }
+
+        frame.render_widget(Paragraph::new(Line::from(stats)).centered(), stats_area);
+
+        // The most recent samples, one per column.
+        let throughput = progress.throughput();
+        let recent = &throughput[throughput.len().saturating_sub(graph_area.width as usize)..];
+        let sparkline = Sparkline::default().data(recent).style(accent);
+
+        frame.render_widget(sparkline, graph_area);
+
+        Ok(())
+    }
+
+    fn render_cancel(&self, frame: &mut Frame) {
+        let (phase, consequence) = match self.resume {
+            Modal::Validating => ("validating", "Nothing has been written yet."),
+            Modal::Copying if self.args.from_drive => {
+                ("copying", "The partial image will not be usable.")
+            }
+            Modal::Copying => ("copying", "The drive will be left partially written."),
+            _ if self.args.from_drive => (
+                "verifying",
+                "The image has been copied and will be kept, but not verified.",
+            ),
+            _ => (
+                "verifying",
+                "The data has been copied but will not be verified.",
+            ),
         };
 
         let lines = vec![
             Line::from(""),
             Line::from(vec![
-                if !self.args.verify {
+                "Do you want to stop ".into(),
+                Span::styled(phase, self.ui_accent),
+                "?".into(),
+            ]),
+            Line::from(consequence),
+            Line::from(""),
+            Line::from(""),
+            Line::from(vec![
+                Span::styled("<esc> ", self.ui_accent),
+                "Keep going".into(),
+                "          ".into(),
+                Span::styled("<y> ", self.ui_accent),
+                "Cancel".into(),
+            ]),
+        ];
+
+        self.render_modal(frame, " Cancel ", lines);
+    }
+
+    fn render_cancelled(&self, frame: &mut Frame) {
+        let progress = self.progress.as_ref().unwrap().lock().unwrap();
+
+        let phase = match self.resume {
+            Modal::Validating => "Validating",
+            Modal::Copying => "Copying",
+            _ => "Verifying",
+        };
+
+        let mut summary = vec![
+            Span::raw(format!("{phase} cancelled at ")),
+            Span::styled(imge::humanize(progress.done), self.ui_accent),
+        ];
+        if progress.size > 0 {
+            summary.push(" of ".into());
+            summary.push(Span::styled(imge::humanize(progress.size), self.ui_accent));
+        }
+        summary.push(".".into());
+
+        let outcome = if self.args.from_drive && self.resume == Modal::Verifying {
+            Line::from(vec![
+                "The image was saved as ".into(),
+                Span::styled(
+                    self.args.image.to_string_lossy().to_string(),
+                    self.ui_accent,
+                ),
+                " without verification.".into(),
+            ])
+        } else if self.args.from_drive && self.args.keep_partial {
+            Line::from(vec![
+                "The partial image was kept as ".into(),
+                Span::styled(
+                    self.partial_path().to_string_lossy().to_string(),
+                    self.ui_accent,
+                ),
+                ".".into(),
+            ])
+        } else if self.args.from_drive {
+            Line::from("The partial image was removed.")
+        } else if self.resume == Modal::Copying {
+            Line::from("The drive is left partially written.")
+        } else {
+            Line::from("")
+        };
+
+        let lines = vec![
+            Line::from(""),
+            Line::from(summary),
+            Line::from(""),
+            outcome,
+            Line::from(""),
+            Line::from(""),
+            Line::from(vec![Span::styled("<esc> ", self.ui_accent), "Close".into()]),
+        ];
+
+        self.render_modal(frame, " Cancelled ", lines);
+    }
+
+    fn render_victory(&self, frame: &mut Frame) {
+        let progress = self.progress.as_ref().unwrap().lock().unwrap();
+
+        let speed = match progress.time.as_secs_f64() {
+            secs if secs > 0.0 => (progress.done as f64 / secs) as u64,
+            _ => progress.done,
+        };
+
+        let lines = vec![
+            Line::from(""),
+            Line::from(vec![
+                if self.manifest.is_some() {
+                    "Verified ".into()
+                } else if self.args.sample.is_some() {
+                    "Copied and sample-verified ".into()
+                } else if !self.args.verify {
                     "Copied ".into()
                 } else {
                     "Copied and verified ".into()
                 },
                 Span::styled(imge::humanize(progress.done), self.ui_accent),
                 " in ".into(),
-                Span::styled(progress.secs.to_string(), self.ui_accent),
+                Span::styled(
+                    format!("{:.1}", progress.time.as_secs_f64()),
+                    self.ui_accent,
+                ),
                 " seconds.".into(),
             ]),
             Line::from(""),
@@ -414,21 +1106,34 @@ impl Mainloop {
                 " per second.".into(),
             ]),
             Line::from(""),
-            Line::from(""),
+            self.header_backup_line(),
             Line::from(vec![Span::styled("<esc> ", self.ui_accent), "Close".into()]),
         ];
 
         self.render_modal(frame, " Victory ", lines);
     }
 
+    fn header_backup_line(&self) -> Line<'_> {
+        match self.header_backup.lock().unwrap().as_ref() {
+            Some(path) => Line::from(vec![
+                "Previous header saved to ".into(),
+                Span::styled(path.to_string_lossy().to_string(), self.ui_accent),
+            ]),
+            None => Line::from(""),
+        }
+    }
+
     fn render_error(&self, frame: &mut Frame) {
         let error = self.error.lock().unwrap();
 
+        let error = error.as_ref().unwrap();
+
         let lines = vec![
             Line::from(""),
-            Line::from(Span::raw(error.as_ref().unwrap().to_string())),
-            Line::from(""),
+            Line::from(Span::raw(error.to_string())),
             Line::from(""),
+            Line::from(explain::hint(error).unwrap_or_default()),
+            self.header_backup_line(),
             Line::from(vec![Span::styled("<esc> ", self.ui_accent), "Close".into()]),
         ];
 
@@ -438,6 +1143,41 @@ impl Mainloop {
     fn handle_events(&mut self, key: KeyEvent) -> Result<()> {
         if key.code == KeyCode::Char('c') && key.modifiers == KeyModifiers::CONTROL {
             self.exit = true;
+        } else if self.modal == Modal::Cancel {
+            match key.code {
+                KeyCode::Char('y') => {
+                    self.progress.as_ref().unwrap().cancel();
+                    self.modal = self.resume;
+                }
+                KeyCode::Char('n') | KeyCode::Esc => self.modal = self.resume,
+                _ => {}
+            }
+        } else if matches!(
+            self.modal,
+            Modal::Validating | Modal::Copying | Modal::Verifying
+        ) {
+            match key.code {
+                KeyCode::Esc => {
+                    self.resume = self.modal;
+                    self.modal = Modal::Cancel;
+                }
+                KeyCode::Char('p') if self.modal != Modal::Validating => {
+                    self.progress.as_ref().unwrap().toggle_pause();
+                }
+                _ => {}
+            }
+        } else if self.modal == Modal::Confirm && key.code != KeyCode::Esc {
+            match key.code {
+                KeyCode::Char(c) => self.confirmation.push(c),
+                KeyCode::Backspace => {
+                    self.confirmation.pop();
+                }
+                KeyCode::Enter if self.confirmation_matches() => {
+                    self.confirmed = true;
+                    self.proceed();
+                }
+                _ => {}
+            }
         } else if key.code == KeyCode::Char('i') {
             match self.modal {
                 Modal::None => self.modal = Modal::Keybindings,
@@ -445,7 +1185,16 @@ impl Mainloop {
                 _ => {}
             }
         } else if self.modal == Modal::Warning && key.code == KeyCode::Enter {
-            self.start_copying();
+            if self.held_by().is_none() {
+                self.proceed();
+            }
+        } else if (self.modal == Modal::Warning || self.modal == Modal::Mounted)
+            && key.code == KeyCode::Char('u')
+            && self.offers_unmount()
+            && self.held_by().is_none()
+        {
+            self.unmount_confirmed = true;
+            self.proceed();
         } else if self.modal == Modal::None {
             match key.code {
                 KeyCode::Char('a') => {
@@ -455,20 +1204,37 @@ impl Mainloop {
                 KeyCode::Char('r') => {
                     self.update_drives(true)?;
                 }
-                KeyCode::Up => {
-                    if self.selected_row > 0 {
-                        self.selected_row -= 1;
-                        self.update_drives(false)?;
-                    }
+                KeyCode::Char('d') => {
+                    self.show_details = !self.show_details;
                 }
-                KeyCode::Down => {
-                    if self.selected_row + 1 < self.drives.len() {
-                        self.selected_row += 1;
-                        self.update_drives(false)?;
+                KeyCode::Right | KeyCode::Left if self.selected_drive.is_some() => {
+                    let name = self.selected_drive.clone().unwrap();
+                    if key.code == KeyCode::Left {
+                        self.expanded.retain(|expanded| *expanded != name);
+                    } else if let Some(i) = self.expanded.iter().position(|e| *e == name) {
+                        self.expanded.remove(i);
+                    } else {
+                        self.expanded.push(name);
                     }
                 }
-                KeyCode::Enter => {
-                    if self.selected_drive.is_some() {
+                KeyCode::Up if self.selected_row > 0 => {
+                    self.highlighted = None;
+                    self.selected_row -= 1;
+                    self.update_drives(false)?;
+                }
+                KeyCode::Down if self.selected_row + 1 < self.drives.len() => {
+                    self.highlighted = None;
+                    self.selected_row += 1;
+                    self.update_drives(false)?;
+                }
+                KeyCode::Enter if self.selected_drive.is_some() => {
+                    self.pinned = self.selected().map(imge::Drive::identity);
+
+                    if let Err(err) = self.check_target() {
+                        *self.error.lock().unwrap() = Some(err);
+                    } else if self.manifest.is_some() {
+                        self.start();
+                    } else {
                         self.modal = Modal::Warning;
                     }
                 }
@@ -479,6 +1245,9 @@ impl Mainloop {
             }
         } else if key.code == KeyCode::Esc {
             self.modal = Modal::None;
+            self.confirmation.clear();
+            self.confirmed = false;
+            self.unmount_confirmed = false;
             self.progress = None;
             *self.error.lock().unwrap() = None;
         }
@@ -488,9 +1257,18 @@ impl Mainloop {
 
     fn update_drives(&mut self, refresh: bool) -> Result<()> {
         if refresh {
-            self.drives = imge::list_drives(self.args.all_drives)?;
+            self.drives = imge::list_drives(self.args.all_drives, self.args.factory, &self.config)?;
             self.selected_row = 0;
 
+            if let Some(drive) = &self.args.drive
+                && !self.drives.iter().any(|d| d.name == *drive)
+            {
+                return Err(anyhow!(
+                    "Drive {} is no longer available",
+                    drive.to_string_lossy()
+                ));
+            }
+
             for i in 0..self.drives.len() {
                 if self.selected_drive == Some(self.drives[i].name.clone()) {
                     self.selected_row = i;
@@ -516,12 +1294,126 @@ impl Mainloop {
         Ok(())
     }
 
+    // Keeps the selection on the same drive, or moves it to a removable
+    // drive that has just been plugged in.
+    fn refresh_drives(&mut self) -> Result<()> {
+        let known: Vec<OsString> = self.drives.iter().map(|d| d.name.clone()).collect();
+        self.update_drives(true)?;
+
+        if let Some(row) = self.drives.iter().position(|d| {
+            d.is_removable
+                && !d.is_locked
+                && (!d.details.is_read_only || self.args.from_drive)
+                && !known.contains(&d.name)
+        }) {
+            self.highlighted = Some(self.drives[row].name.clone());
+
+            if self.config.auto_select {
+                self.selected_row = row;
+                self.update_drives(false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&imge::Drive> {
+        let selected_drive = self.selected_drive.as_ref()?;
+        self.drives
+            .iter()
+            .find(|drive| drive.name == *selected_drive)
+    }
+
+    fn check_target(&self) -> Result<()> {
+        if self.args.from_drive || self.manifest.is_some() {
+            return Ok(());
+        }
+
+        match self.selected() {
+            Some(drive) if drive.details.is_read_only => {
+                Err(anyhow!(imge::write_protected(&drive.name)))
+            }
+            // No option overrides the configuration.
+            Some(drive) if drive.is_locked => Err(anyhow!(
+                "{} is protected by the imge configuration and will not be written.",
+                drive.name.to_string_lossy()
+            )),
+            _ if self.args.force_system_drive => Ok(()),
+            Some(drive) if drive.is_system => Err(anyhow!(
+                "{} holds the running system (/, /boot, /home or swap). \
+                 Use --force-system-drive if you really mean it.",
+                drive.name.to_string_lossy()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn held_by(&self) -> Option<&Vec<String>> {
+        if self.args.from_drive || self.manifest.is_some() {
+            return None;
+        }
+
+        self.selected()
+            .map(|drive| &drive.holders)
+            .filter(|holders| !holders.is_empty())
+    }
+
+    fn offers_unmount(&self) -> bool {
+        // Copying from a drive or verifying it against a manifest only reads it.
+        !self.args.from_drive
+            && self.manifest.is_none()
+            && self.selected().is_some_and(|drive| drive.is_mounted)
+    }
+
+    fn needs_typed_confirmation(&self) -> bool {
+        if self.args.from_drive || self.manifest.is_some() || self.confirmed {
+            return false;
+        }
+
+        self.selected().is_some_and(|drive| {
+            !drive.is_removable || drive.size > self.args.confirm_size.saturating_mul(1 << 30)
+        })
+    }
+
+    fn confirmation_matches(&self) -> bool {
+        let Some(drive) = self.selected() else {
+            return false;
+        };
+        let typed = self.confirmation.trim();
+        let drive_name = Path::new(&drive.name)
+            .file_name()
+            .unwrap()
+            .to_string_lossy();
+        let serial = drive.serial.trim();
+
+        typed == drive_name
+            || typed == drive.name
+            || (serial.len() >= 4
+                && serial.is_char_boundary(serial.len() - 4)
+                && typed.eq_ignore_ascii_case(&serial[serial.len() - 4..]))
+    }
+
+    // Walks the remaining confirmations after the warning, then starts.
+    fn proceed(&mut self) {
+        if self.needs_typed_confirmation() {
+            self.modal = Modal::Confirm;
+        } else if self.needs_mounted_confirmation() {
+            self.modal = Modal::Mounted;
+        } else {
+            self.start();
+        }
+    }
+
+    fn needs_mounted_confirmation(&self) -> bool {
+        self.offers_unmount() && !self.args.unmount && !self.unmount_confirmed
+    }
+
     fn get_volumes(&self) -> (imge::Volume, imge::Volume) {
         let image = imge::Volume {
             vtype: imge::VolumeType::Image,
-            path: self.args.image.clone(),
+            path: self.image_target.clone(),
             size: if self.image_compression == imge::Compression::None {
-                match fs::metadata(&self.args.image) {
+                match fs::metadata(&self.image_target) {
                     Ok(metadata) => {
                         if metadata.file_type().is_char_device() {
                             Some(self.selected_size)
@@ -535,6 +1427,7 @@ impl Mainloop {
                 None
             },
             compression: self.image_compression,
+            identity: None,
         };
 
         let drive = imge::Volume {
@@ -542,13 +1435,145 @@ impl Mainloop {
             path: self.selected_drive.clone().unwrap(),
             size: Some(self.selected_size),
             compression: imge::Compression::None,
+            identity: self.pinned.clone(),
         };
 
         (image, drive)
     }
 
+    fn partial_path(&self) -> OsString {
+        let mut path = self.args.image.clone();
+        path.push(".partial");
+        path
+    }
+
+    // Keeps or removes the output of a cancelled drive-to-image copy.
+    // An image that was copied completely is kept even when its
+    // verification was cancelled.
+    fn finish_cancel(&mut self) {
+        if self.args.from_drive
+            && self.image_target != self.args.image
+            && fs::exists(&self.image_target).unwrap_or_default()
+        {
+            let result = match self.args.keep_partial {
+                _ if self.resume == Modal::Verifying => {
+                    fs::rename(&self.image_target, &self.args.image)
+                }
+                true => fs::rename(&self.image_target, self.partial_path()),
+                false => fs::remove_file(&self.image_target),
+            };
+            if let Err(err) = result {
+                *self.error.lock().unwrap() = Some(anyhow!(err));
+                return;
+            }
+        }
+
+        self.modal = Modal::Cancelled;
+    }
+
+    // Asks a running phase to stop and waits until it has flushed and closed
+    // its files, but no longer than the stall timeout, since a worker blocked
+    // on a hung drive would otherwise keep the terminal in raw mode forever.
+    fn stop_worker(&mut self) -> Result<()> {
+        if let Some(progress) = &self.progress {
+            progress.cancel();
+        }
+        let Some(worker) = self.worker.take() else {
+            return Ok(());
+        };
+
+        let timeout = Duration::from_secs(self.args.stall_timeout);
+        let started = Instant::now();
+        while !worker.is_finished() {
+            if !timeout.is_zero() && started.elapsed() > timeout {
+                let drive = self.selected_drive.clone().unwrap_or_default();
+                let drive = Path::new(&drive).file_name().unwrap_or_default();
+                return Err(anyhow!(io::Error::other(format!(
+                    "{} did not respond for {} seconds after the transfer was stopped, \
+                     it was left behind. Unplug it and start over.",
+                    drive.to_string_lossy(),
+                    timeout.as_secs()
+                ))));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = worker.join();
+
+        Ok(())
+    }
+
+    fn watch(&mut self, progress: imge::ProgressMutex) {
+        let timeout = Duration::from_secs(self.args.stall_timeout);
+        let drive = self.selected_drive.clone().unwrap_or_default();
+
+        self.watchdog = Some(Watchdog::new(&drive, timeout));
+        self.progress = Some(progress);
+    }
+
+    // Moves a finished drive-to-image copy into place.
+    fn rename_image(&self) -> Result<()> {
+        if self.image_target != self.args.image && fs::exists(&self.image_target)? {
+            fs::rename(&self.image_target, &self.args.image)?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) {
+        self.confirmation.clear();
+        self.confirmed = false;
+        let unmount = self.args.unmount || self.unmount_confirmed;
+        self.unmount_confirmed = false;
+        *self.header_backup.lock().unwrap() = None;
+
+        if unmount && self.manifest.is_none() && self.offers_unmount() {
+            match imge::unmount(self.selected_drive.as_ref().unwrap()) {
+                Ok(mountpoints) => self.unmounted = mountpoints,
+                Err(err) => {
+                    *self.error.lock().unwrap() = Some(err);
+                    return;
+                }
+            }
+        }
+
+        if self.manifest.is_some() {
+            self.start_verifying_manifest();
+        } else if self.checksum.is_some() || self.signature.is_some() {
+            self.start_validating();
+        } else {
+            self.start_copying();
+        }
+    }
+
+    fn start_validating(&mut self) {
+        let checksum = self.checksum.clone();
+        let signature = self.signature.clone();
+        let path = self.args.image.clone();
+        let error = self.error.clone();
+
+        let progress = imge::SharedProgress::new(imge::Progress {
+            size: fs::metadata(&path).map(|m| m.len()).unwrap_or_default(),
+            ..Default::default()
+        });
+
+        self.watch(progress.clone());
+        self.modal = Modal::Validating;
+
+        self.worker = Some(thread::spawn(move || {
+            let result =
+                imge::validate(&path, checksum.as_deref(), signature.as_deref(), &progress);
+            if let Err(err) = result
+                && !progress.lock().unwrap().cancelled
+            {
+                *error.lock().unwrap() = Some(err);
+            }
+        }));
+    }
+
     fn start_copying(&mut self) {
         let (image, drive) = self.get_volumes();
+        let manifest = self.args.manifest.clone();
+        let header_backup = self.header_backup.clone();
         let error = self.error.clone();
 
         let (src, dest) = match self.args.from_drive {
@@ -556,20 +1581,28 @@ impl Mainloop {
             true => (drive, image),
         };
 
-        let progress = Arc::new(Mutex::new(imge::Progress {
+        let progress = imge::SharedProgress::new(imge::Progress {
             size: src.size.unwrap_or_default(),
             ..Default::default()
-        }));
+        });
 
-        self.progress = Some(progress.clone());
+        self.watch(progress.clone());
         self.modal = Modal::Copying;
 
-        thread::spawn(move || {
-            let result = imge::copy(&src, &dest, &progress);
-            if let Err(err) = result {
+        self.worker = Some(thread::spawn(move || {
+            let result = match dest.vtype {
+                imge::VolumeType::Drive => header::backup(&dest).and_then(|path| {
+                    *header_backup.lock().unwrap() = Some(path);
+                    imge::copy(&src, &dest, manifest.as_ref(), &progress)
+                }),
+                imge::VolumeType::Image => imge::copy(&src, &dest, manifest.as_ref(), &progress),
+            };
+            if let Err(err) = result
+                && !progress.lock().unwrap().cancelled
+            {
                 *error.lock().unwrap() = Some(err);
             }
-        });
+        }));
     }
 
     fn start_verifying(&mut self) -> Result<()> {
@@ -582,27 +1615,105 @@ impl Mainloop {
         }
 
         let copying_progress = self.progress.as_ref().unwrap().lock().unwrap();
-        let progress = Arc::new(Mutex::new(imge::Progress {
+        let progress = imge::SharedProgress::new(imge::Progress {
             size: if copying_progress.size > 0 {
                 copying_progress.size
             } else {
                 copying_progress.done
             },
-            secs: copying_progress.secs,
+            time: copying_progress.time,
             ..Default::default()
-        }));
+        });
         drop(copying_progress);
 
-        self.progress = Some(progress.clone());
+        self.watch(progress.clone());
         self.modal = Modal::Verifying;
 
-        thread::spawn(move || {
-            let result = imge::verify(&image, &drive, &progress);
-            if let Err(err) = result {
+        let sample = self.args.sample;
+        let size = progress.lock().unwrap().size;
+
+        self.worker = Some(thread::spawn(move || {
+            let result = match sample {
+                Some(samples) => imge::verify_sampled(&image, &drive, size, samples, &progress),
+                None => imge::verify(&image, &drive, &progress),
+            };
+            if let Err(err) = result
+                && !progress.lock().unwrap().cancelled
+            {
                 *error.lock().unwrap() = Some(err);
             }
-        });
+        }));
 
         Ok(())
     }
+
+    fn start_verifying_manifest(&mut self) {
+        let (_, drive) = self.get_volumes();
+        let manifest = self.manifest.clone().unwrap();
+        let error = self.error.clone();
+
+        let progress = imge::SharedProgress::new(imge::Progress {
+            size: manifest.size,
+            ..Default::default()
+        });
+
+        self.watch(progress.clone());
+        self.modal = Modal::Verifying;
+
+        self.worker = Some(thread::spawn(move || {
+            let result = imge::verify_manifest(&manifest, &drive, &progress);
+            if let Err(err) = result
+                && !progress.lock().unwrap().cancelled
+            {
+                *error.lock().unwrap() = Some(err);
+            }
+        }));
+    }
+}
+
+fn clock(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+const FLUSHING_LABEL: &str = "Writing cached data to the image";
+
+fn worker_panic(payload: Box<dyn Any + Send>) -> Error {
+    let message = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::from("unknown error"),
+        },
+    };
+
+    anyhow!(io::Error::other(format!(
+        "The transfer crashed: {message}. Please report this as a bug."
+    )))
+}
+
+fn paused_label(progress: &imge::Progress) -> String {
+    format!(
+        "Paused after {}, press <p> to resume",
+        clock(progress.elapsed())
+    )
+}
+
+fn gauge_label(progress: &imge::Progress) -> String {
+    match progress.paused {
+        false if progress.flushing => {
+            format!("{:.1} %, {FLUSHING_LABEL}", progress.percents() * 100.0)
+        }
+        false => format!("{:.1} %", progress.percents() * 100.0),
+        true => format!(
+            "{:.1} %, {}",
+            progress.percents() * 100.0,
+            paused_label(progress)
+        ),
+    }
 }
@@ -0,0 +1,244 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+const HEADER: &str = "imge-manifest 1";
+const BLOCK_SIZE: u64 = 1024 * 1024;
+// Blocks are read into memory one at a time.
+const MAX_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+pub struct Manifest {
+    pub block_size: u64,
+    pub size: u64,
+    pub digests: Vec<(u64, String)>,
+}
+
+pub struct ManifestWriter {
+    file: BufWriter<File>,
+    hasher: Sha256,
+    offset: u64,
+    filled: u64,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn digest(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+impl ManifestWriter {
+    pub fn create(path: &OsStr) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{HEADER}")?;
+        writeln!(file, "block-size {BLOCK_SIZE}")?;
+
+        Ok(ManifestWriter {
+            file,
+            hasher: Sha256::new(),
+            offset: 0,
+            filled: 0,
+        })
+    }
+
+    fn write_digest(&mut self) -> Result<()> {
+        let digest = hex(&self.hasher.finalize_reset());
+        writeln!(self.file, "{} {digest}", self.offset)?;
+
+        self.offset += self.filled;
+        self.filled = 0;
+
+        Ok(())
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let len = data.len().min((BLOCK_SIZE - self.filled) as usize);
+            self.hasher.update(&data[..len]);
+            self.filled += len as u64;
+            data = &data[len..];
+
+            if self.filled == BLOCK_SIZE {
+                self.write_digest()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        if self.filled > 0 {
+            self.write_digest()?;
+        }
+
+        writeln!(self.file, "size {}", self.offset)?;
+        self.file.flush()?;
+
+        Ok(())
+    }
+}
+
+pub fn read(path: &OsStr) -> Result<Manifest> {
+    let name = path.to_string_lossy();
+    let invalid = || anyhow!("{name} is not a valid imge manifest");
+
+    let content = fs::read_to_string(path)?;
+    let mut lines = content.lines();
+
+    if lines.next() != Some(HEADER) {
+        return Err(invalid());
+    }
+
+    let mut manifest = Manifest {
+        block_size: 0,
+        size: 0,
+        digests: Vec::new(),
+    };
+
+    for line in lines {
+        let (key, value) = line.split_once(' ').ok_or_else(invalid)?;
+        match key {
+            "block-size" => manifest.block_size = value.parse().map_err(|_| invalid())?,
+            "size" => manifest.size = value.parse().map_err(|_| invalid())?,
+            offset => manifest
+                .digests
+                .push((offset.parse().map_err(|_| invalid())?, value.to_string())),
+        }
+    }
+
+    if manifest.block_size == 0
+        || manifest.block_size > MAX_BLOCK_SIZE
+        || !manifest.block_size.is_multiple_of(4096)
+        || manifest.size == 0
+    {
+        return Err(invalid());
+    }
+
+    // Exactly one digest for each block, in order, so that no part
+    // of the data goes unverified.
+    let blocks = manifest.size.div_ceil(manifest.block_size);
+    let complete = manifest.digests.len() as u64 == blocks
+        && manifest
+            .digests
+            .iter()
+            .enumerate()
+            .all(|(i, (offset, digest))| {
+                *offset == i as u64 * manifest.block_size
+                    && digest.len() == 64
+                    && digest
+                        .bytes()
+                        .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+            });
+    if !complete {
+        return Err(invalid());
+    }
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("imge-{}-{name}", process::id()))
+    }
+
+    fn read_str(name: &str, content: &str) -> Result<Manifest> {
+        let path = temp_path(name);
+        fs::write(&path, content).unwrap();
+        let manifest = read(path.as_os_str());
+        fs::remove_file(&path).unwrap();
+        manifest
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..BLOCK_SIZE * 2 + 1000).map(|i| i as u8).collect();
+        let path = temp_path("round-trip.manifest");
+
+        let mut writer = ManifestWriter::create(path.as_os_str()).unwrap();
+        writer.update(&data[..1000]).unwrap();
+        writer.update(&data[1000..]).unwrap();
+        writer.finish().unwrap();
+        let manifest = read(path.as_os_str());
+        fs::remove_file(&path).unwrap();
+        let manifest = manifest.unwrap();
+
+        let block = BLOCK_SIZE as usize;
+        assert_eq!(manifest.block_size, BLOCK_SIZE);
+        assert_eq!(manifest.size, data.len() as u64);
+        assert_eq!(
+            manifest.digests,
+            vec![
+                (0, digest(&data[..block])),
+                (BLOCK_SIZE, digest(&data[block..block * 2])),
+                (BLOCK_SIZE * 2, digest(&data[block * 2..])),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_manifests() {
+        let hex = digest(b"");
+        let valid = format!("imge-manifest 1\nblock-size 4096\n0 {hex}\n4096 {hex}\nsize 5000\n");
+        assert!(read_str("valid", &valid).is_ok());
+
+        let cases = [
+            (
+                "no-header",
+                format!("block-size 4096\n0 {hex}\nsize 4096\n"),
+            ),
+            (
+                "no-digests",
+                String::from("imge-manifest 1\nblock-size 4096\nsize 4096\n"),
+            ),
+            (
+                "zero-size",
+                format!("imge-manifest 1\nblock-size 4096\n0 {hex}\nsize 0\n"),
+            ),
+            (
+                "unaligned",
+                format!("imge-manifest 1\nblock-size 1000\n0 {hex}\nsize 1000\n"),
+            ),
+            (
+                "huge-block",
+                format!("imge-manifest 1\nblock-size 1099511627776\n0 {hex}\nsize 4096\n"),
+            ),
+            (
+                "missing-block",
+                format!("imge-manifest 1\nblock-size 4096\n0 {hex}\nsize 8192\n"),
+            ),
+            (
+                "gap",
+                format!("imge-manifest 1\nblock-size 4096\n0 {hex}\n8192 {hex}\nsize 8192\n"),
+            ),
+            (
+                "short-digest",
+                String::from("imge-manifest 1\nblock-size 4096\n0 abc\nsize 4096\n"),
+            ),
+            (
+                "uppercase",
+                format!(
+                    "imge-manifest 1\nblock-size 4096\n0 {}\nsize 4096\n",
+                    hex.to_uppercase()
+                ),
+            ),
+            ("garbage", String::from("imge-manifest 1\nblock-size\n")),
+        ];
+
+        for (name, content) in cases {
+            assert!(read_str(name, &content).is_err(), "{name} was accepted");
+        }
+    }
+}
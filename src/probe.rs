@@ -0,0 +1,170 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+// Far enough to reach the btrfs superblock at 64 KiB.
+const PROBE_SIZE: usize = 68 * 1024;
+
+#[derive(Default)]
+pub struct Filesystem {
+    pub fstype: String,
+    pub label: String,
+    pub uuid: String,
+}
+
+fn bytes(data: &[u8], offset: usize, len: usize) -> &[u8] {
+    data.get(offset..offset + len).unwrap_or_default()
+}
+
+fn le16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes(data, offset, 2).try_into().unwrap_or_default())
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes(data, offset, 4).try_into().unwrap_or_default())
+}
+
+fn label(data: &[u8], offset: usize, len: usize) -> String {
+    let label = bytes(data, offset, len);
+    let end = label
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(label.len());
+    String::from_utf8_lossy(&label[..end]).trim().to_string()
+}
+
+fn uuid(data: &[u8], offset: usize) -> String {
+    let uuid: Vec<String> = bytes(data, offset, 16)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    if uuid.len() < 16 {
+        return String::new();
+    }
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        uuid[0..4].concat(),
+        uuid[4..6].concat(),
+        uuid[6..8].concat(),
+        uuid[8..10].concat(),
+        uuid[10..16].concat()
+    )
+}
+
+// FAT and exFAT volume serials are shown as XXXX-XXXX, NTFS ones as 16 hex digits.
+fn serial(data: &[u8], offset: usize, len: usize) -> String {
+    let serial: String = bytes(data, offset, len)
+        .iter()
+        .rev()
+        .map(|byte| format!("{byte:02X}"))
+        .collect();
+
+    match len {
+        4 if serial.len() == 8 => format!("{}-{}", &serial[..4], &serial[4..]),
+        _ => serial,
+    }
+}
+
+fn ext(data: &[u8]) -> Option<Filesystem> {
+    const SB: usize = 1024;
+    if le16(data, SB + 56) != 0xef53 {
+        return None;
+    }
+
+    let compat = le32(data, SB + 92);
+    let incompat = le32(data, SB + 96);
+    let fstype = if incompat & 0x2c0 != 0 {
+        "ext4"
+    } else if compat & 0x4 != 0 {
+        "ext3"
+    } else {
+        "ext2"
+    };
+
+    Some(Filesystem {
+        fstype: fstype.to_string(),
+        label: label(data, SB + 120, 16),
+        uuid: uuid(data, SB + 104),
+    })
+}
+
+fn fat(data: &[u8]) -> Option<Filesystem> {
+    if le16(data, 510) != 0xaa55 {
+        return None;
+    }
+
+    if bytes(data, 3, 8) == b"EXFAT   " {
+        return Some(Filesystem {
+            fstype: "exfat".to_string(),
+            label: String::new(),
+            uuid: serial(data, 100, 4),
+        });
+    }
+    if bytes(data, 3, 8) == b"NTFS    " {
+        return Some(Filesystem {
+            fstype: "ntfs".to_string(),
+            label: String::new(),
+            uuid: serial(data, 72, 8),
+        });
+    }
+    if bytes(data, 82, 5) == b"FAT32" {
+        return Some(Filesystem {
+            fstype: "vfat".to_string(),
+            label: label(data, 71, 11),
+            uuid: serial(data, 67, 4),
+        });
+    }
+    if bytes(data, 54, 3) == b"FAT" {
+        return Some(Filesystem {
+            fstype: "vfat".to_string(),
+            label: label(data, 43, 11),
+            uuid: serial(data, 39, 4),
+        });
+    }
+
+    None
+}
+
+fn other(data: &[u8]) -> Option<Filesystem> {
+    let (fstype, label, uuid) = if bytes(data, 0, 6) == b"LUKS\xba\xbe" {
+        ("crypto_LUKS", String::new(), label(data, 168, 40))
+    } else if bytes(data, 0, 4) == b"XFSB" {
+        ("xfs", label(data, 108, 12), uuid(data, 32))
+    } else if bytes(data, 0, 4) == b"hsqs" {
+        ("squashfs", String::new(), String::new())
+    } else if bytes(data, 65536 + 64, 8) == b"_BHRfS_M" {
+        (
+            "btrfs",
+            label(data, 65536 + 299, 256),
+            uuid(data, 65536 + 32),
+        )
+    } else if bytes(data, 4086, 10) == b"SWAPSPACE2" {
+        ("swap", label(data, 1024 + 28, 16), uuid(data, 1024 + 12))
+    } else if bytes(data, 32769, 5) == b"CD001" {
+        ("iso9660", label(data, 32768 + 40, 32), String::new())
+    } else {
+        return None;
+    };
+
+    Some(Filesystem {
+        fstype: fstype.to_string(),
+        label,
+        uuid,
+    })
+}
+
+/// Identifies the filesystem on a partition from its superblock, `None` when
+/// the device cannot be read or holds nothing recognizable.
+pub fn probe(path: &OsStr) -> Option<Filesystem> {
+    let file = File::open(path).ok()?;
+    let mut data = vec![0u8; PROBE_SIZE];
+    let len = file.read_at(&mut data, 0).ok()?;
+    data.truncate(len);
+
+    ext(&data).or_else(|| fat(&data)).or_else(|| other(&data))
+}
@@ -0,0 +1,271 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use anyhow::{anyhow, Result};
+use minisign_verify::{PublicKey, StreamVerifier};
+use pgp::composed::{Deserializable, DetachedSignature, SignedPublicKey};
+use pgp::packet::{PublicKey as PgpPrimaryKey, PublicSubkey};
+use pgp::types::KeyDetails;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+enum Key {
+    Minisign(PublicKey),
+    OpenPgp(Box<SignedPublicKey>),
+}
+
+enum Detached {
+    Minisign(minisign_verify::Signature),
+    OpenPgp(DetachedSignature),
+}
+
+pub struct Signature {
+    key: Key,
+    signature: Detached,
+    pub source: String,
+}
+
+// The primary key or subkey of an OpenPGP certificate that made a signature.
+#[derive(Clone)]
+enum PgpSigner {
+    Primary(PgpPrimaryKey),
+    Subkey(PublicSubkey),
+}
+
+// Feeds the image to `pgp::packet::Signature::verify`, which only takes
+// a reader, from the blocks the validation loop hands over.
+struct ChannelReader {
+    blocks: Receiver<Vec<u8>>,
+    block: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            match self.blocks.recv() {
+                Ok(block) => {
+                    self.block = block;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buffer.len().min(self.block.len() - self.position);
+        buffer[..len].copy_from_slice(&self.block[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+pub struct PgpVerifier {
+    blocks: SyncSender<Vec<u8>>,
+    worker: JoinHandle<pgp::errors::Result<()>>,
+}
+
+pub enum Verifier<'a> {
+    Minisign(Box<StreamVerifier<'a>>),
+    OpenPgp(PgpVerifier),
+}
+
+impl Verifier<'_> {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Verifier::Minisign(verifier) => verifier.update(data),
+            // A verifier that gave up early reports why in `finalize`.
+            Verifier::OpenPgp(verifier) => {
+                let _ = verifier.blocks.send(data.to_vec());
+            }
+        }
+    }
+
+    pub fn finalize(self) -> Result<()> {
+        match self {
+            Verifier::Minisign(mut verifier) => Ok(verifier.finalize()?),
+            Verifier::OpenPgp(verifier) => {
+                drop(verifier.blocks);
+                verifier
+                    .worker
+                    .join()
+                    .map_err(|_| anyhow!("OpenPGP verification failed"))??;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn user_id(key: &SignedPublicKey) -> String {
+    key.details
+        .users
+        .first()
+        .map(|user| String::from_utf8_lossy(user.id.id()).to_string())
+        .unwrap_or_else(|| "OpenPGP key".to_string())
+}
+
+impl Signature {
+    pub fn signer(&self) -> String {
+        match (&self.key, &self.signature) {
+            (Key::Minisign(key), Detached::Minisign(signature)) => {
+                let key = key
+                    .untrusted_comment()
+                    .and_then(|comment| comment.strip_prefix("untrusted comment: "))
+                    .unwrap_or("minisign public key");
+
+                format!("{key}, {}", signature.trusted_comment())
+            }
+            (Key::OpenPgp(key), _) => {
+                format!("{}, {:X}", user_id(key), key.primary_key.fingerprint())
+            }
+            _ => String::new(),
+        }
+    }
+
+    // Picks the primary key or signing subkey named as the issuer.
+    fn pgp_signer(
+        &self,
+        key: &SignedPublicKey,
+        signature: &DetachedSignature,
+    ) -> Result<PgpSigner> {
+        let signature = &signature.signature;
+        let issued_by = |details: &dyn KeyDetails| {
+            let (key_ids, fingerprints) =
+                (signature.issuer_key_id(), signature.issuer_fingerprint());
+            (key_ids.is_empty() && fingerprints.is_empty())
+                || key_ids.contains(&&details.legacy_key_id())
+                || fingerprints.contains(&&details.fingerprint())
+        };
+
+        if issued_by(&key.primary_key) {
+            return Ok(PgpSigner::Primary(key.primary_key.clone()));
+        }
+
+        key.public_subkeys
+            .iter()
+            .find(|subkey| {
+                issued_by(&subkey.key) && subkey.signatures.iter().any(|sig| sig.key_flags().sign())
+            })
+            .map(|subkey| PgpSigner::Subkey(subkey.key.clone()))
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} was not made with the configured public key",
+                    self.source
+                )
+            })
+    }
+
+    pub fn verifier(&self) -> Result<Verifier<'_>> {
+        match (&self.key, &self.signature) {
+            (Key::Minisign(key), Detached::Minisign(signature)) => key
+                .verify_stream(signature)
+                .map(|verifier| Verifier::Minisign(Box::new(verifier)))
+                .map_err(|err| match err {
+                    minisign_verify::Error::UnexpectedKeyId => {
+                        anyhow!(
+                            "{} was not made with the configured public key",
+                            self.source
+                        )
+                    }
+                    minisign_verify::Error::UnsupportedLegacyMode => anyhow!(
+                        "{} is a legacy signature, re-sign the image with minisign -H",
+                        self.source
+                    ),
+                    err => anyhow!(err),
+                }),
+            (Key::OpenPgp(key), Detached::OpenPgp(signature)) => {
+                let signer = self.pgp_signer(key, signature)?;
+                let signature = signature.signature.clone();
+                let (blocks, receiver) = mpsc::sync_channel(4);
+                let reader = ChannelReader {
+                    blocks: receiver,
+                    block: Vec::new(),
+                    position: 0,
+                };
+
+                let worker = thread::spawn(move || match signer {
+                    PgpSigner::Primary(key) => signature.verify(&key, reader),
+                    PgpSigner::Subkey(key) => signature.verify(&key, reader),
+                });
+
+                Ok(Verifier::OpenPgp(PgpVerifier { blocks, worker }))
+            }
+            (Key::Minisign(_), Detached::OpenPgp(_)) => Err(anyhow!(
+                "{} is an OpenPGP signature, but the public key is a minisign key",
+                self.source
+            )),
+            (Key::OpenPgp(_), Detached::Minisign(_)) => Err(anyhow!(
+                "{} is a minisign signature, but the public key is an OpenPGP key",
+                self.source
+            )),
+        }
+    }
+}
+
+fn public_key(arg: &OsStr) -> Result<Key> {
+    if let Ok(key) = PublicKey::from_base64(&arg.to_string_lossy()) {
+        return Ok(Key::Minisign(key));
+    }
+
+    let name = arg.to_string_lossy();
+    let content = fs::read(arg).map_err(|err| anyhow!("Cannot read public key {name}: {err}"))?;
+
+    if content.starts_with(b"untrusted comment: ") {
+        let key = PublicKey::decode(&String::from_utf8_lossy(&content))
+            .map_err(|err| anyhow!("{name}: {err}"))?;
+        return Ok(Key::Minisign(key));
+    }
+
+    // Armored or binary OpenPGP certificate.
+    let (key, _) = SignedPublicKey::from_reader_single(&content[..])
+        .map_err(|err| anyhow!("{name} is neither a minisign nor an OpenPGP public key: {err}"))?;
+    key.verify_bindings()
+        .map_err(|err| anyhow!("{name}: invalid OpenPGP key: {err}"))?;
+    if !key.details.revocation_signatures.is_empty() {
+        return Err(anyhow!("{name}: the OpenPGP key has been revoked"));
+    }
+
+    Ok(Key::OpenPgp(Box::new(key)))
+}
+
+pub fn find(image: &Path, key: &OsStr) -> Result<Signature> {
+    let key = public_key(key)?;
+    let filename = image.file_name().unwrap().to_string_lossy();
+    let dirname = image.parent().unwrap_or(Path::new(""));
+
+    for ext in ["minisig", "sig", "asc"] {
+        let path = dirname.join(format!("{filename}.{ext}"));
+        if !path.is_file() {
+            continue;
+        }
+
+        let source = path.file_name().unwrap().to_string_lossy().to_string();
+        let content = fs::read_to_string(&path).unwrap_or_default();
+
+        let signature = if content.starts_with("untrusted comment: ") {
+            Detached::Minisign(
+                minisign_verify::Signature::decode(&content)
+                    .map_err(|err| anyhow!("{source}: {err}"))?,
+            )
+        } else {
+            let file = BufReader::new(File::open(&path)?);
+            let (signature, _) = DetachedSignature::from_reader_single(file)
+                .map_err(|err| anyhow!("{source} is not a minisign or OpenPGP signature: {err}"))?;
+            Detached::OpenPgp(signature)
+        };
+
+        return Ok(Signature {
+            key,
+            signature,
+            source,
+        });
+    }
+
+    Err(anyhow!(
+        "{filename} is not signed, no {filename}.minisig, .sig or .asc found next to it"
+    ))
+}
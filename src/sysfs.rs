@@ -0,0 +1,367 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+const SYSTEM_MOUNTPOINTS: [&str; 3] = ["/", "/boot", "/home"];
+
+pub fn read(path: impl AsRef<Path>) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?;
+    Some(value.trim().to_string())
+}
+
+fn class_path(name: &str) -> PathBuf {
+    Path::new("/sys/class/block").join(name)
+}
+
+fn entries(path: &Path) -> Vec<String> {
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn wwn(disk: &str) -> String {
+    let path = class_path(disk);
+    read(path.join("device/wwid"))
+        .or_else(|| read(path.join("wwid")))
+        .unwrap_or_default()
+}
+
+#[derive(Default)]
+pub struct Details {
+    pub transport: String,
+    pub vendor: String,
+    pub revision: String,
+    pub logical_block_size: u64,
+    pub physical_block_size: u64,
+    pub is_read_only: bool,
+    pub is_rotational: bool,
+    pub supports_discard: bool,
+    pub usb_path: String,
+}
+
+fn transport(disk: &str, device: &Path) -> &'static str {
+    let device = device.to_string_lossy();
+
+    if device.contains("/usb") {
+        "USB"
+    } else if disk.starts_with("nvme") {
+        "NVMe"
+    } else if disk.starts_with("mmcblk") {
+        "MMC/SD"
+    } else if device.contains("/ata") {
+        "SATA"
+    } else if device.contains("/virtio") {
+        "virtio"
+    } else if device.contains("/host") {
+        "SCSI"
+    } else {
+        ""
+    }
+}
+
+// The port chain of a USB device, like 2-1.4 for port 4 of the hub
+// on port 1 of bus 2.
+fn usb_path(device: &Path) -> String {
+    device
+        .iter()
+        .map(|component| component.to_string_lossy())
+        .rev()
+        .find(|component| {
+            component.split_once('-').is_some_and(|(bus, ports)| {
+                !bus.is_empty()
+                    && bus.chars().all(|c| c.is_ascii_digit())
+                    && ports.chars().all(|c| c.is_ascii_digit() || c == '.')
+            })
+        })
+        .map(|component| component.to_string())
+        .unwrap_or_default()
+}
+
+pub fn details(disk: &str) -> Details {
+    let path = class_path(disk);
+    let device = fs::canonicalize(path.join("device")).unwrap_or_default();
+    let number = |name: &str| {
+        read(path.join(name))
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or_default()
+    };
+
+    Details {
+        transport: transport(disk, &device).to_string(),
+        // virtio and PCI devices only have a numeric vendor ID here.
+        vendor: read(path.join("device/vendor"))
+            .filter(|vendor| !vendor.starts_with("0x"))
+            .unwrap_or_default(),
+        revision: ["device/rev", "device/firmware_rev", "device/fwrev"]
+            .iter()
+            .find_map(|name| read(path.join(name)))
+            .unwrap_or_default(),
+        logical_block_size: number("queue/logical_block_size"),
+        physical_block_size: number("queue/physical_block_size"),
+        is_read_only: number("ro") != 0,
+        is_rotational: number("queue/rotational") != 0,
+        supports_discard: number("queue/discard_max_bytes") != 0,
+        usb_path: usb_path(&device),
+    }
+}
+
+fn describe_holder(name: &str) -> String {
+    let path = class_path(name);
+
+    if name.starts_with("md") {
+        let level = read(path.join("md/level")).unwrap_or_default();
+        return format!("{name} (md {level})");
+    }
+
+    if name.starts_with("dm-") {
+        let uuid = read(path.join("dm/uuid")).unwrap_or_default();
+        let kind = if uuid.starts_with("CRYPT-") {
+            "dm-crypt"
+        } else if uuid.starts_with("LVM-") {
+            "LVM"
+        } else {
+            "device-mapper"
+        };
+        let dm_name = read(path.join("dm/name")).unwrap_or_default();
+        return format!("{name} ({kind} {dm_name})");
+    }
+
+    name.to_string()
+}
+
+fn collect_holders(path: &Path, holders: &mut Vec<String>) {
+    for holder in entries(&path.join("holders")) {
+        let description = describe_holder(&holder);
+        if !holders.contains(&description) {
+            holders.push(description);
+            collect_holders(&class_path(&holder), holders);
+        }
+    }
+}
+
+/// Device-mapper, md and LVM devices stacked on the disk or its partitions.
+pub fn holders(disk: &str) -> Vec<String> {
+    let path = class_path(disk);
+    let mut holders = Vec::new();
+
+    collect_holders(&path, &mut holders);
+    for entry in entries(&path) {
+        if entry.starts_with(disk) {
+            collect_holders(&path.join(entry), &mut holders);
+        }
+    }
+
+    holders
+}
+
+// Whole disks backing a block device, following partitions up to their disk
+// and device-mapper/md devices down to their slaves.
+fn disks_of(path: &Path, disks: &mut HashSet<String>) {
+    let Ok(path) = fs::canonicalize(path) else {
+        return;
+    };
+
+    if path.join("partition").exists() {
+        if let Some(parent) = path.parent() {
+            disks_of(parent, disks);
+        }
+        return;
+    }
+
+    let slaves = entries(&path.join("slaves"));
+
+    if slaves.is_empty() {
+        if let Some(name) = path.file_name() {
+            disks.insert(name.to_string_lossy().to_string());
+        }
+    } else {
+        for slave in slaves {
+            disks_of(&class_path(&slave), disks);
+        }
+    }
+}
+
+pub fn devno_path(devno: u64) -> PathBuf {
+    let (major, minor) = (libc::major(devno), libc::minor(devno));
+    PathBuf::from(format!("/sys/dev/block/{major}:{minor}"))
+}
+
+fn disks_of_devno(devno: u64, disks: &mut HashSet<String>) {
+    disks_of(&devno_path(devno), disks);
+}
+
+// Block device behind a mount source or a swap file.
+fn devno_of(path: &str) -> Option<u64> {
+    let metadata = fs::metadata(path).ok()?;
+    if metadata.file_type().is_file() {
+        Some(metadata.dev())
+    } else {
+        Some(metadata.rdev())
+    }
+}
+
+// Mountinfo escapes spaces, tabs, newlines and backslashes as octal.
+fn unescape(field: &str) -> String {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'\\'
+            && tail.len() >= 3
+            && let Ok(code) = u8::from_str_radix(&String::from_utf8_lossy(&tail[..3]), 8)
+        {
+            bytes.push(code);
+            rest = &tail[3..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+struct MountInfo {
+    devno: Option<u64>,
+    mountpoint: String,
+    source: String,
+}
+
+fn parse_mountinfo(text: &str) -> Vec<MountInfo> {
+    let mut mounts = Vec::new();
+
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let Some(separator) = fields.iter().position(|field| *field == "-") else {
+            continue;
+        };
+        if fields.len() < separator + 3 || separator < 5 {
+            continue;
+        }
+
+        let source = unescape(fields[separator + 2]);
+
+        // Btrfs and friends report an anonymous 0:N device here,
+        // fall back to the mount source.
+        let devno = match fields[2].split_once(':') {
+            Some((major, minor)) if major != "0" => Some(libc::makedev(
+                major.parse().unwrap_or_default(),
+                minor.parse().unwrap_or_default(),
+            )),
+            _ => devno_of(&source),
+        };
+
+        mounts.push(MountInfo {
+            devno,
+            mountpoint: unescape(fields[4]),
+            source,
+        });
+    }
+
+    mounts
+}
+
+fn mountinfo() -> Vec<MountInfo> {
+    parse_mountinfo(&fs::read_to_string("/proc/self/mountinfo").unwrap_or_default())
+}
+
+/// Mounted filesystems living on the disk, as (source, mountpoint) pairs
+/// in mount order.
+pub fn mounts_of(disk: &str) -> Vec<(String, String)> {
+    let mut mounts = Vec::new();
+
+    for mount in mountinfo() {
+        let mut disks = HashSet::new();
+        if let Some(devno) = mount.devno {
+            disks_of_devno(devno, &mut disks);
+        }
+
+        if disks.contains(disk) {
+            mounts.push((mount.source, mount.mountpoint));
+        }
+    }
+
+    mounts
+}
+
+/// Names of the disks holding `/`, `/boot`, `/home` or active swap.
+pub fn system_disks() -> HashSet<String> {
+    let mut disks = HashSet::new();
+
+    for mount in mountinfo() {
+        if SYSTEM_MOUNTPOINTS.contains(&mount.mountpoint.as_str())
+            && let Some(devno) = mount.devno
+        {
+            disks_of_devno(devno, &mut disks);
+        }
+    }
+
+    for line in fs::read_to_string("/proc/swaps")
+        .unwrap_or_default()
+        .lines()
+        .skip(1)
+    {
+        if let Some(devno) = line.split_whitespace().next().and_then(devno_of) {
+            disks_of_devno(devno, &mut disks);
+        }
+    }
+
+    disks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mountinfo_lines() {
+        let text = "\
+36 25 8:3 / / rw,relatime shared:1 - ext4 /dev/sda3 rw
+37 36 8:17 / /media/My\\040Disk rw,nosuid - vfat /dev/sdb1 rw
+38 36 0:45 /@home /home rw master:2 shared:3 - btrfs /nonexistent/imge rw
+39 36 8:18 / /broken
+";
+        let mounts = parse_mountinfo(text);
+
+        assert_eq!(mounts.len(), 3);
+        assert_eq!(mounts[0].devno, Some(libc::makedev(8, 3)));
+        assert_eq!(mounts[0].mountpoint, "/");
+        assert_eq!(mounts[0].source, "/dev/sda3");
+        assert_eq!(mounts[1].devno, Some(libc::makedev(8, 17)));
+        assert_eq!(mounts[1].mountpoint, "/media/My Disk");
+        assert_eq!(mounts[2].devno, None);
+        assert_eq!(mounts[2].mountpoint, "/home");
+        assert_eq!(mounts[2].source, "/nonexistent/imge");
+    }
+
+    #[test]
+    fn unescape_mountinfo() {
+        assert_eq!(unescape("/media/My\\040Disk"), "/media/My Disk");
+        assert_eq!(unescape("/a\\011b\\012c\\134d"), "/a\tb\nc\\d");
+        assert_eq!(unescape("/plain"), "/plain");
+        assert_eq!(unescape("/end\\04"), "/end\\04");
+        assert_eq!(unescape("/bad\\9xy"), "/bad\\9xy");
+    }
+
+    #[test]
+    fn usb_port_chain() {
+        let device = Path::new(
+            "/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1.4/2-1.4:1.0/host6/target6:0:0/6:0:0:0",
+        );
+        assert_eq!(usb_path(device), "2-1.4");
+
+        let device =
+            Path::new("/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0");
+        assert_eq!(usb_path(device), "");
+    }
+}
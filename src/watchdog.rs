@@ -0,0 +1,68 @@
+//  This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::imge::{self, Progress};
+use anyhow::{anyhow, Result};
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Notices the drive of a running phase disappearing from sysfs, or its
+/// progress standing still for longer than the timeout, since a pulled or
+/// hung device may block the worker in a read or write forever.
+pub struct Watchdog {
+    drive: String,
+    timeout: Duration,
+    read: u64,
+    moved: Instant,
+}
+
+impl Watchdog {
+    /// A zero `timeout` only watches for the drive being disconnected.
+    pub fn new(drive: &OsStr, timeout: Duration) -> Self {
+        Watchdog {
+            drive: Path::new(drive)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            timeout,
+            read: 0,
+            moved: Instant::now(),
+        }
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        !Path::new("/sys/block").join(&self.drive).exists()
+    }
+
+    pub fn check(&mut self, progress: &Progress) -> Result<()> {
+        if self.is_disconnected() {
+            return Err(anyhow!(io::Error::other(format!(
+                "{} was disconnected after {} of data. Plug it in again and start over.",
+                self.drive,
+                imge::humanize(progress.done)
+            ))));
+        }
+
+        if progress.read != self.read || progress.paused || progress.flushing || progress.finished {
+            self.read = progress.read;
+            self.moved = Instant::now();
+            return Ok(());
+        }
+
+        if !self.timeout.is_zero() && self.moved.elapsed() > self.timeout {
+            return Err(anyhow!(io::Error::other(format!(
+                "{} stalled at {}, no data was transferred for {} seconds. \
+                 Unplug it, check the port or reader and start over.",
+                self.drive,
+                imge::humanize(progress.done),
+                self.timeout.as_secs()
+            ))));
+        }
+
+        Ok(())
+    }
+}